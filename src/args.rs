@@ -1,15 +1,212 @@
 use {
-    crate::{defaults, logic::validate_target, misc::sanitize_target_string},
+    crate::{
+        defaults,
+        logic::validate_target,
+        misc::{parse_delimiter, parse_duration, sanitize_target_string},
+        resolutions::Resolution,
+    },
     chrono::Utc,
-    clap::Parser,
-    std::{collections::HashSet, time::Instant},
+    clap::{Parser, Subcommand, ValueEnum},
+    std::{
+        collections::{HashMap, HashSet},
+        str::FromStr,
+        time::{Duration, Instant},
+    },
 };
 
+/// Default --host-timeout in seconds when --fast-scan is used
+const FAST_SCAN_HOST_TIMEOUT: u64 = 20 * 60;
+
+/// Available port scanning backends
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScannerBackend {
+    /// Nmap scans every IP on its own
+    Nmap,
+    /// masscan finds the open ports, then Nmap detects services only on them
+    Masscan,
+    /// Built-in TCP connect scanner, no privileges needed. Nmap detects services on the open ports if it's available
+    Connect,
+}
+
+impl ScannerBackend {
+    /// Whether the backend needs Nmap, either to scan or to detect services
+    #[must_use]
+    pub const fn uses_nmap(self, fast_scan: bool) -> bool {
+        match self {
+            Self::Nmap => true,
+            Self::Masscan | Self::Connect => !fast_scan,
+        }
+    }
+}
+
+/// What to do with the IPs that belong to a CDN or a cloud WAF
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CdnMode {
+    /// Scan them like any other IP, only tag them in the results
+    Tag,
+    /// Don't scan them
+    Skip,
+    /// Only scan the usual web ports (80, 443, 8080 and 8443)
+    WebPorts,
+}
+
+/// Which IPs to scan depending on whether they belong to a cloud provider
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CloudFilter {
+    /// Only scan the IPs found in the cloud ranges
+    Only,
+    /// Don't scan the IPs found in the cloud ranges
+    Exclude,
+}
+
+/// What every line of the JSON Lines output describes
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JsonlMode {
+    /// One object per host, with all its ports
+    Host,
+    /// One object per host and open port
+    Port,
+}
+
+/// Columns of the CSV output
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CsvColumn {
+    Host,
+    /// Scanned IP
+    Ip,
+    /// Every IP the host resolved to
+    Ips,
+    Port,
+    Protocol,
+    State,
+    Reason,
+    Service,
+    Version,
+    Product,
+    /// OS type reported by the service
+    Os,
+    Extrainfo,
+    Tunnel,
+    /// OS guess of Nmap OS detection
+    OsGuess,
+    Status,
+    ScanError,
+    Ptr,
+    Asn,
+    AsOrg,
+    Country,
+    Cdn,
+    CloudProvider,
+    CloudService,
+    CloudRegion,
+}
+
+/// Formats of the outputs of a run
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// The default table, only on stdout
+    Table,
+    /// One row per host, IP and port, see --csv-columns
+    Csv,
+    /// HOST:PORT lines
+    Url,
+    /// A single JSON document
+    Json,
+    /// One JSON object per host
+    Jsonl,
+    /// One JSON object per host and port
+    JsonlPort,
+    /// Nmap grepable format (-oG)
+    Grepable,
+    /// Markdown table
+    Markdown,
+    /// Self-contained HTML report, only to a file
+    Html,
+    /// Merged Nmap XML of all the IPs, only to a file
+    NmapXml,
+    /// SQLite database, every run is appended to it. Only to a file
+    Sqlite,
+}
+
+/// An output of the run, written to `path` or to stdout when it's None
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutputSink {
+    pub format: OutputFormat,
+    pub path: Option<String>,
+}
+
+impl OutputSink {
+    #[must_use]
+    pub const fn stdout(format: OutputFormat) -> Self {
+        Self { format, path: None }
+    }
+
+    #[must_use]
+    pub const fn file(format: OutputFormat, path: String) -> Self {
+        Self {
+            format,
+            path: Some(path),
+        }
+    }
+}
+
+/// Parse `FORMAT` or `FORMAT:FILE`
+impl FromStr for OutputSink {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (format, path) = match value.split_once(':') {
+            Some((format, path)) if !path.is_empty() => (format, Some(path.to_string())),
+            _ => (value.trim_end_matches(':'), None),
+        };
+        let format = OutputFormat::from_str(format, true)?;
+        match (format, &path) {
+            (OutputFormat::Table, Some(_)) => Err(String::from(
+                "the table output can only be printed to stdout",
+            )),
+            (OutputFormat::Html | OutputFormat::NmapXml | OutputFormat::Sqlite, None) => {
+                Err(format!("the {value} output needs a file, use {value}:FILE"))
+            }
+            _ => Ok(Self { format, path }),
+        }
+    }
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Compare two results files and print what changed: new and removed hosts, IP changes, opened and closed ports and service changes
+    Diff(DiffArgs),
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct DiffArgs {
+    /// Old results, a --json, --jsonl (host or port) file or a --sqlite database
+    pub old: String,
+
+    /// New results, a --json, --jsonl (host or port) file or a --sqlite database. With the same database as OLD, its last two runs are compared
+    pub new: String,
+
+    /// Run of the OLD database to compare. Defaults to its latest run
+    #[arg(long)]
+    pub old_run: Option<i64>,
+
+    /// Run of the NEW database to compare. Defaults to its latest run
+    #[arg(long)]
+    pub new_run: Option<i64>,
+
+    /// Print the changes as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
 /// Scan only once by IP address and reduce scan times with Nmap for large amounts of data.
 #[derive(Parser, Debug, Clone)]
 #[command(author = "Eduard Tolosa <edu4rdshl@protonmail.com>", version, about, long_about = None)]
 #[command(arg_required_else_help = true)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Target host
     #[arg(short, long, conflicts_with_all = ["files", "stdin"])]
     pub target: Option<String>,
@@ -19,11 +216,11 @@ pub struct Args {
     pub files: Vec<String>,
 
     /// Write to an output file. The name of the output file will be unimap-log-date
-    #[arg(short, long, conflicts_with = "unique_output")]
+    #[arg(short, long)]
     pub output: bool,
 
     /// Write the output in CSV format to the specified filename
-    #[arg(short, long, conflicts_with = "output")]
+    #[arg(short, long)]
     pub unique_output: Option<String>,
 
     /// Remove informative messages
@@ -50,6 +247,78 @@ pub struct Args {
     #[arg(long)]
     pub fast_scan: bool,
 
+    /// Connect to open ports without a service detected by Nmap and identify them from their banner or a few common probes (HTTP, TLS, SSH, SMTP, FTP, Redis)
+    #[arg(long)]
+    pub banners: bool,
+
+    /// Request every open web port with each hostname that points to the IP and report the status code, title, server, redirect and length per hostname
+    #[arg(long)]
+    pub http_probe: bool,
+
+    /// Look up the PTR records of every resolved IP and show them next to the IP
+    #[arg(long)]
+    pub reverse_dns: bool,
+
+    /// Offline IP to ASN database used to annotate every IP with its ASN, organisation and country: an iptoasn.com TSV file (ip2asn-v4.tsv or ip2asn-combined.tsv, optionally .gz) or a MaxMind-style .mmdb file
+    #[arg(long)]
+    pub asn_db: Option<String>,
+
+    /// Print a summary of the hosts and IPs grouped by ASN
+    #[arg(long, requires = "asn_db")]
+    pub group_by_asn: bool,
+
+    /// Detect the IPs of CDNs and cloud WAFs (Cloudflare, Akamai, Fastly, Imperva, Sucuri...) from their IP ranges and the CNAMEs of the hostnames, tag them in the results and decide what to do with them
+    #[arg(long, value_enum)]
+    pub cdn: Option<CdnMode>,
+
+    /// File with extra or updated CDN ranges, one "<provider> <cidr>" per line. They are checked before the bundled ones
+    #[arg(long, requires = "cdn")]
+    pub cdn_ranges: Option<String>,
+
+    /// IP ranges files published by the cloud providers, used to tag every IP with its provider, region and service. The AWS (ip-ranges.json), Google Cloud (cloud.json), Azure (ServiceTags_Public_*.json) and Oracle Cloud (public_ip_ranges.json) formats are detected automatically
+    #[arg(long)]
+    pub cloud_ranges: Vec<String>,
+
+    /// Only scan the cloud-hosted IPs or exclude them from the scan
+    #[arg(long, value_enum, requires = "cloud_ranges")]
+    pub cloud_filter: Option<CloudFilter>,
+
+    /// Fetch the TLS certificate chain of every open port that completes a handshake and report its subject, SANs, issuer, validity and SHA-256 fingerprint. Names found in the certificates that are not targets are reported as new hostnames
+    #[arg(long)]
+    pub tls_certs: bool,
+
+    /// Resolve and scan the new hostnames found in the TLS certificates in a second round
+    #[arg(long, requires = "tls_certs")]
+    pub resolve_new_hosts: bool,
+
+    /// Maximum time to spend scanning a single IP, e.g. 500ms, 900s, 30m or 2h. Passed to Nmap as --host-timeout and enforced by killing Nmap if it doesn't stop. Defaults to 20m with --fast-scan and no limit otherwise
+    #[arg(long, value_parser = parse_duration)]
+    pub host_timeout: Option<Duration>,
+
+    /// Reuse the <ip>.xml files of previous scans in the logs directory instead of scanning again when they are not older than MAX_AGE (e.g. 12h or 7d) and were run with the same ports and scan options
+    #[arg(long, value_name = "MAX_AGE", value_parser = parse_duration)]
+    pub scan_cache: Option<Duration>,
+
+    /// Enable Nmap OS detection (-O --osscan-guess). Requires root/administrator privileges
+    #[arg(short = 'O', long)]
+    pub os_detection: bool,
+
+    /// Port scanning backend. With masscan or connect, Nmap only runs service detection on the open ports they find
+    #[arg(long, value_enum, default_value_t = ScannerBackend::Nmap)]
+    pub scanner: ScannerBackend,
+
+    /// Path to the masscan binary. If it's only a name, it's searched in the PATH
+    #[arg(long, default_value = "masscan")]
+    pub masscan_path: String,
+
+    /// Packets (masscan) or connections (connect) per second sent by the port discovery backend
+    #[arg(long, default_value_t = 1000, value_parser = clap::value_parser!(u32).range(1..))]
+    pub rate: u32,
+
+    /// Path to the Nmap binary. If it's only a name, it's searched in the PATH
+    #[arg(long, default_value = "nmap")]
+    pub nmap_path: String,
+
     /// Path to save the CSV data of the process and/or Nmap XML files. Default to logs/
     #[arg(long, default_value = "unimap_logs")]
     pub logs_dir: String,
@@ -58,14 +327,78 @@ pub struct Args {
     #[arg(short = 'k', long)]
     pub no_keep_nmap_logs: bool,
 
-    /// Use raw output instead of a table
-    #[arg(short, long, conflicts_with = "url_output")]
+    /// Use raw output instead of a table. Same as --out csv
+    #[arg(short, long)]
     pub raw_output: bool,
 
-    /// Use HOST:IP output format
-    #[arg(long, conflicts_with = "raw_output")]
+    /// Use HOST:PORT output format. Same as --out url
+    #[arg(long)]
     pub url_output: bool,
 
+    /// Add an output, FORMAT to print it or FORMAT:FILE to write it to a file. Can be used several times, e.g. --out jsonl:results.jsonl --out html:report.html. Only one output can be printed, the table is printed when none is
+    #[arg(long, value_name = "FORMAT[:FILE]")]
+    pub out: Vec<OutputSink>,
+
+    /// Comma separated list of the columns of the CSV output (-r, -o and -u). Defaults to host,ip,port,service,version,product,os,extrainfo,os-guess,status plus the columns of the enabled enrichments
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub csv_columns: Vec<CsvColumn>,
+
+    /// Field delimiter of the CSV output, a single ASCII character or "tab"
+    #[arg(long, default_value = ",", value_parser = parse_delimiter)]
+    pub csv_delimiter: u8,
+
+    /// Don't write the header row of the CSV output
+    #[arg(long)]
+    pub no_csv_header: bool,
+
+    /// Write a self-contained HTML report to the specified filename, with summary statistics, a sortable host table and the details of every IP. Same as --out html:FILE
+    #[arg(long, value_name = "FILE")]
+    pub html: Option<String>,
+
+    /// Merge the Nmap results of every IP into a single Nmap XML file, with all the hostnames of each IP, for tools like Metasploit db_import, Faraday or DefectDojo. Same as --out nmap-xml:FILE
+    #[arg(long, value_name = "FILE")]
+    pub nmap_xml: Option<String>,
+
+    /// Append the results to a SQLite database, creating it if needed. Every run gets its own ID, see the README for the schema. Same as --out sqlite:FILE
+    #[arg(long, value_name = "FILE")]
+    pub sqlite: Option<String>,
+
+    /// Print the results as a single JSON document instead of a table. See the README for the schema. Same as --out json
+    #[arg(long)]
+    pub json: bool,
+
+    /// Print the results as JSON Lines instead of a table, one object per host (default) or per host and port. Same as --out jsonl or --out jsonl-port
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "host")]
+    pub jsonl: Option<JsonlMode>,
+
+    /// Print the results in the Nmap grepable format (-oG) instead of a table. Same as --out grepable
+    #[arg(long)]
+    pub grepable: bool,
+
+    /// Print the results as a Markdown table instead of a table. Same as --out markdown
+    #[arg(long)]
+    pub markdown: bool,
+
+    /// Compare the results with a previous run, a --json, --jsonl (host or port) file or a --sqlite database, and print only what changed instead of the results. The outputs written to files still get the full results
+    #[arg(long, value_name = "FILE")]
+    pub baseline: Option<String>,
+
+    /// Run of the --baseline database to compare with. Defaults to its latest run
+    #[arg(long, requires = "baseline")]
+    pub baseline_run: Option<i64>,
+
+    /// Resume an interrupted run from the state file in the logs directory, only the targets not resolved and the IPs not scanned yet are processed. Use the same targets and scan options
+    #[arg(long)]
+    pub resume: bool,
+
+    /// Don't resolve or scan anything: build the results from the Nmap XML files in DIR and the IPs of the --resolutions file, e.g. for scans run on another machine. DIR is used as the logs directory
+    #[arg(long, value_name = "DIR", requires = "resolutions", conflicts_with_all = ["banners", "http_probe", "reverse_dns", "tls_certs", "resume", "scan_cache", "no_keep_nmap_logs"])]
+    pub import_xml: Option<String>,
+
+    /// File with the IPs of the targets, they are not resolved again: "host,ip" lines, the massdns simple (-o S) or ndjson (-o J) output or the dnsx JSON output (-json). The targets not in the file are resolved as usual. Without -t, -f or --stdin the targets are the hosts of the file
+    #[arg(long, value_name = "FILE")]
+    pub resolutions: Option<String>,
+
     /// Read from stdin instead of files or arguments
     #[arg(long, conflicts_with_all = ["files", "target"])]
     pub stdin: bool,
@@ -84,17 +417,47 @@ impl Args {
             }
         });
 
-        let file_name = if self.output {
-            format!(
+        // The imported XML files take the place of the Nmap logs
+        let logs_dir = self.import_xml.clone().unwrap_or(self.logs_dir);
+
+        let mut outputs = Vec::new();
+        if self.output {
+            let file_name = format!(
                 "{}/unimap{}",
-                self.logs_dir,
+                logs_dir,
                 Utc::now().format("-log-%Y-%m-%d_%H-%M-%S")
-            ) + ".csv"
-        } else if let Some(unique_output) = &self.unique_output {
-            unique_output.clone()
-        } else {
-            String::new()
-        };
+            ) + ".csv";
+            outputs.push(OutputSink::file(OutputFormat::Csv, file_name));
+        }
+        if let Some(unique_output) = self.unique_output {
+            outputs.push(OutputSink::file(OutputFormat::Csv, unique_output));
+        }
+        if let Some(html) = self.html {
+            outputs.push(OutputSink::file(OutputFormat::Html, html));
+        }
+        if let Some(nmap_xml) = self.nmap_xml {
+            outputs.push(OutputSink::file(OutputFormat::NmapXml, nmap_xml));
+        }
+        if let Some(sqlite) = self.sqlite {
+            outputs.push(OutputSink::file(OutputFormat::Sqlite, sqlite));
+        }
+        for (enabled, format) in [
+            (self.raw_output, OutputFormat::Csv),
+            (self.url_output, OutputFormat::Url),
+            (self.json, OutputFormat::Json),
+            (self.jsonl == Some(JsonlMode::Host), OutputFormat::Jsonl),
+            (self.jsonl == Some(JsonlMode::Port), OutputFormat::JsonlPort),
+            (self.grepable, OutputFormat::Grepable),
+            (self.markdown, OutputFormat::Markdown),
+        ] {
+            if enabled {
+                outputs.push(OutputSink::stdout(format));
+            }
+        }
+        outputs.extend(self.out);
+        if outputs.iter().all(|output| output.path.is_some()) {
+            outputs.push(OutputSink::stdout(OutputFormat::Table));
+        }
 
         let threads = if self.ports.is_some() && self.threads.is_none() {
             30
@@ -102,10 +465,49 @@ impl Args {
             self.threads.unwrap_or(50)
         };
 
+        let fast_scan = self.fast_scan;
+        let host_timeout = self
+            .host_timeout
+            .or_else(|| fast_scan.then(|| Duration::from_secs(FAST_SCAN_HOST_TIMEOUT)));
+
         let custom_resolvers_flag = !self.custom_resolvers.is_empty();
         let custom_ports_range = self.ports.is_some();
         let ports_value = self.ports.unwrap_or_default();
 
+        let csv_columns = if self.csv_columns.is_empty() {
+            let mut columns = vec![
+                CsvColumn::Host,
+                CsvColumn::Ip,
+                CsvColumn::Port,
+                CsvColumn::Service,
+                CsvColumn::Version,
+                CsvColumn::Product,
+                CsvColumn::Os,
+                CsvColumn::Extrainfo,
+                CsvColumn::OsGuess,
+                CsvColumn::Status,
+            ];
+            if self.reverse_dns {
+                columns.push(CsvColumn::Ptr);
+            }
+            if self.asn_db.is_some() {
+                columns.extend([CsvColumn::Asn, CsvColumn::AsOrg, CsvColumn::Country]);
+            }
+            if self.cdn.is_some() {
+                columns.push(CsvColumn::Cdn);
+            }
+            if !self.cloud_ranges.is_empty() {
+                columns.extend([
+                    CsvColumn::CloudProvider,
+                    CsvColumn::CloudService,
+                    CsvColumn::CloudRegion,
+                ]);
+            }
+            columns
+        } else {
+            self.csv_columns
+        };
+
         let resolvers = if custom_resolvers_flag {
             self.custom_resolvers
         } else {
@@ -114,22 +516,47 @@ impl Args {
 
         ProcessedArgs {
             target,
-            file_name,
             version: env!("CARGO_PKG_VERSION").to_string(),
-            logs_dir: self.logs_dir,
+            logs_dir,
             threads,
             ports: ports_value,
-            with_output: self.output || self.unique_output.is_some(),
-            unique_output_flag: self.unique_output.is_some(),
             min_rate: self.min_rate.unwrap_or_default(),
             from_file_flag: !self.files.is_empty(),
             quiet_flag: self.quiet,
             custom_resolvers: custom_resolvers_flag,
             custom_ports_range,
             no_keep_nmap_logs: self.no_keep_nmap_logs,
-            raw_output: self.raw_output,
             fast_scan: self.fast_scan,
-            url_output: self.url_output,
+            host_timeout,
+            scan_cache: self.scan_cache,
+            banners: self.banners,
+            http_probe: self.http_probe,
+            reverse_dns: self.reverse_dns,
+            asn_db: self.asn_db,
+            group_by_asn: self.group_by_asn,
+            cdn: self.cdn,
+            cdn_ranges: self.cdn_ranges,
+            cloud_ranges: self.cloud_ranges,
+            cloud_filter: self.cloud_filter,
+            tls_certs: self.tls_certs,
+            resolve_new_hosts: self.resolve_new_hosts,
+            os_detection: self.os_detection,
+            scanner: self.scanner,
+            masscan_path: self.masscan_path,
+            rate: self.rate,
+            nmap_path: self.nmap_path,
+            scan_type: String::from("-sS"),
+            nmap_privileged_flag: false,
+            csv_columns,
+            csv_delimiter: self.csv_delimiter,
+            csv_header: !self.no_csv_header,
+            outputs,
+            baseline: self.baseline,
+            baseline_run: self.baseline_run,
+            resume: self.resume,
+            import_xml: self.import_xml,
+            resolutions: self.resolutions,
+            imported_resolutions: HashMap::new(),
             from_stdin: self.stdin,
             files: self.files,
             resolvers,
@@ -143,25 +570,62 @@ impl Args {
 #[derive(Clone, Debug)]
 pub struct ProcessedArgs {
     pub target: String,
-    pub file_name: String,
     pub version: String,
     pub logs_dir: String,
     pub threads: usize,
     pub ports: String,
-    pub with_output: bool,
-    pub unique_output_flag: bool,
     pub min_rate: String,
     pub from_file_flag: bool,
     pub quiet_flag: bool,
     pub custom_resolvers: bool,
     pub custom_ports_range: bool,
     pub no_keep_nmap_logs: bool,
-    pub raw_output: bool,
     pub fast_scan: bool,
-    pub url_output: bool,
+    pub host_timeout: Option<Duration>,
+    pub scan_cache: Option<Duration>,
+    pub banners: bool,
+    pub http_probe: bool,
+    pub reverse_dns: bool,
+    pub asn_db: Option<String>,
+    pub group_by_asn: bool,
+    pub cdn: Option<CdnMode>,
+    pub cdn_ranges: Option<String>,
+    pub cloud_ranges: Vec<String>,
+    pub cloud_filter: Option<CloudFilter>,
+    pub tls_certs: bool,
+    pub resolve_new_hosts: bool,
+    pub os_detection: bool,
+    pub scanner: ScannerBackend,
+    pub masscan_path: String,
+    pub rate: u32,
+    pub nmap_path: String,
+    pub scan_type: String,
+    pub nmap_privileged_flag: bool,
+    pub csv_columns: Vec<CsvColumn>,
+    pub csv_delimiter: u8,
+    pub csv_header: bool,
+    pub outputs: Vec<OutputSink>,
+    pub baseline: Option<String>,
+    pub baseline_run: Option<i64>,
+    pub resume: bool,
+    pub import_xml: Option<String>,
+    pub resolutions: Option<String>,
+    /// Resolutions read from the --resolutions file
+    pub imported_resolutions: HashMap<String, Resolution>,
     pub from_stdin: bool,
     pub files: Vec<String>,
     pub resolvers: Vec<String>,
     pub targets: HashSet<String>,
     pub time_wasted: Instant,
 }
+
+impl ProcessedArgs {
+    /// Format of the output printed to stdout, if any
+    #[must_use]
+    pub fn stdout_format(&self) -> Option<OutputFormat> {
+        self.outputs
+            .iter()
+            .find(|output| output.path.is_none())
+            .map(|output| output.format)
+    }
+}
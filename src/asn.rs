@@ -0,0 +1,221 @@
+use {
+    crate::errors::{Result, ResultExt},
+    failure::bail,
+    flate2::read::GzDecoder,
+    maxminddb::Reader,
+    std::{
+        fs::File,
+        io::{BufRead, BufReader, Read},
+        net::IpAddr,
+        path::Path,
+    },
+};
+
+/// Autonomous system an IP belongs to
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct AsnInfo {
+    pub number: u32,
+    pub org: String,
+    pub country: Option<String>,
+}
+
+impl AsnInfo {
+    #[must_use]
+    pub fn name(&self) -> String {
+        format!("AS{}", self.number)
+    }
+}
+
+/// Offline IP to ASN dataset, either the iptoasn.com TSV files
+/// (ip2asn-v4.tsv, ip2asn-combined.tsv, optionally gzipped) or a
+/// MaxMind-style mmdb file like GeoLite2-ASN
+pub enum AsnDatabase {
+    Ranges(Vec<AsnRange>),
+    Mmdb(Reader<Vec<u8>>),
+}
+
+pub struct AsnRange {
+    start: IpAddr,
+    end: IpAddr,
+    info: AsnInfo,
+}
+
+/// Fields of the GeoLite2-ASN records and of the ipinfo.io ASN databases
+#[derive(Deserialize)]
+struct MmdbRecord {
+    autonomous_system_number: Option<u32>,
+    autonomous_system_organization: Option<String>,
+    asn: Option<String>,
+    as_name: Option<String>,
+    country: Option<String>,
+}
+
+impl AsnDatabase {
+    pub fn open(path: &str) -> Result<Self> {
+        if Path::new(path)
+            .extension()
+            .is_some_and(|extension| extension == "mmdb")
+        {
+            let reader = Reader::open_readfile(path)
+                .with_context(|_| format!("Can't open the ASN database {path}"))?;
+            return Ok(Self::Mmdb(reader));
+        }
+
+        let file =
+            File::open(path).with_context(|_| format!("Can't open the ASN database {path}"))?;
+        let reader: Box<dyn Read> = if path.ends_with(".gz") {
+            Box::new(GzDecoder::new(file))
+        } else {
+            Box::new(file)
+        };
+        Self::from_tsv(BufReader::new(reader), path)
+    }
+
+    fn from_tsv(reader: impl BufRead, path: &str) -> Result<Self> {
+        let mut ranges = Vec::new();
+        for line in reader.lines() {
+            let line = line.with_context(|_| format!("Can't read the ASN database {path}"))?;
+            if let Some(range) = parse_tsv_line(&line) {
+                ranges.push(range);
+            }
+        }
+        if ranges.is_empty() {
+            bail!(
+                "No IP ranges found in {}, is it an iptoasn TSV or mmdb file?",
+                path
+            )
+        }
+        ranges.sort_by_key(|range| range.start);
+        Ok(Self::Ranges(ranges))
+    }
+
+    #[must_use]
+    pub fn lookup(&self, ip: IpAddr) -> Option<AsnInfo> {
+        match self {
+            Self::Ranges(ranges) => {
+                let position = ranges.partition_point(|range| range.start <= ip);
+                ranges
+                    .get(position.checked_sub(1)?)
+                    .filter(|range| ip <= range.end)
+                    .map(|range| range.info.clone())
+            }
+            Self::Mmdb(reader) => {
+                let record: MmdbRecord = reader.lookup(ip).ok()?;
+                let number = record.autonomous_system_number.or_else(|| {
+                    record
+                        .asn
+                        .as_deref()
+                        .and_then(|asn| asn.trim_start_matches("AS").parse().ok())
+                })?;
+                Some(AsnInfo {
+                    number,
+                    org: record
+                        .autonomous_system_organization
+                        .or(record.as_name)
+                        .unwrap_or_default(),
+                    country: record.country,
+                })
+            }
+        }
+    }
+}
+
+/// range_start, range_end, AS_number, country_code, AS_description
+fn parse_tsv_line(line: &str) -> Option<AsnRange> {
+    let mut fields = line.split('\t');
+    let start = fields.next()?.trim().parse().ok()?;
+    let end = fields.next()?.trim().parse().ok()?;
+    let number = fields.next()?.trim().parse().ok()?;
+    // AS 0 marks the ranges that are not routed
+    if number == 0 {
+        return None;
+    }
+    let country = fields
+        .next()
+        .map(str::trim)
+        .filter(|country| !country.is_empty() && *country != "None")
+        .map(str::to_string);
+    let org = fields.next().unwrap_or_default().trim().to_string();
+    Some(AsnRange {
+        start,
+        end,
+        info: AsnInfo {
+            number,
+            org,
+            country,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Unsorted on purpose, with the "Not routed" rows of the iptoasn files
+    const TSV: &str = "\
+8.8.8.0\t8.8.8.255\t15169\tUS\tGOOGLE
+1.0.0.0\t1.0.0.255\t13335\tUS\tCLOUDFLARENET
+1.0.1.0\t1.0.3.255\t0\tNone\tNot routed
+2606:4700::\t2606:4700:ffff:ffff:ffff:ffff:ffff:ffff\t13335\tUS\tCLOUDFLARENET
+1.0.4.0\t1.0.7.255\t38803\tAU\tGTELECOM-AUSTRALIA Gtelecom Pty Ltd
+10.0.0.0\t10.255.255.255\t0\tNone\tNot routed
+not an ip\t1.2.3.4\t1\tUS\tBROKEN
+5.0.0.0\t5.0.0.255\t64500\tNone\t
+";
+
+    fn lookup(database: &AsnDatabase, ip: &str) -> Option<AsnInfo> {
+        database.lookup(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn parse_iptoasn_tsv() {
+        let database = AsnDatabase::from_tsv(TSV.as_bytes(), "ip2asn.tsv").unwrap();
+        match &database {
+            AsnDatabase::Ranges(ranges) => assert_eq!(ranges.len(), 5),
+            AsnDatabase::Mmdb(_) => panic!("expected TSV ranges"),
+        }
+
+        let google = lookup(&database, "8.8.8.8").unwrap();
+        assert_eq!(google.name(), "AS15169");
+        assert_eq!(google.org, "GOOGLE");
+        assert_eq!(google.country.as_deref(), Some("US"));
+
+        let gtelecom = lookup(&database, "1.0.5.1").unwrap();
+        assert_eq!(gtelecom.org, "GTELECOM-AUSTRALIA Gtelecom Pty Ltd");
+
+        let unnamed = lookup(&database, "5.0.0.1").unwrap();
+        assert_eq!(unnamed.number, 64500);
+        assert_eq!(unnamed.org, "");
+        assert_eq!(unnamed.country, None);
+
+        assert!(
+            AsnDatabase::from_tsv("1.0.1.0\t1.0.3.255\t0\tNone\tNot routed\n".as_bytes(), "x")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn lookup_range_boundaries() {
+        let database = AsnDatabase::from_tsv(TSV.as_bytes(), "ip2asn.tsv").unwrap();
+
+        let number = |ip| lookup(&database, ip).map(|info| info.number);
+        assert_eq!(number("0.255.255.255"), None);
+        assert_eq!(number("1.0.0.0"), Some(13335));
+        assert_eq!(number("1.0.0.255"), Some(13335));
+        // Not routed
+        assert_eq!(number("1.0.1.0"), None);
+        assert_eq!(number("1.0.3.255"), None);
+        assert_eq!(number("1.0.4.0"), Some(38803));
+        assert_eq!(number("1.0.7.255"), Some(38803));
+        assert_eq!(number("1.0.8.0"), None);
+        assert_eq!(number("10.1.2.3"), None);
+        assert_eq!(number("8.8.7.255"), None);
+        assert_eq!(number("8.8.8.255"), Some(15169));
+        assert_eq!(number("8.8.9.0"), None);
+        assert_eq!(number("255.255.255.255"), None);
+        assert_eq!(number("2606:4700::"), Some(13335));
+        assert_eq!(number("2606:4700:ffff::1"), Some(13335));
+        assert_eq!(number("2606:4701::"), None);
+        assert_eq!(number("::1"), None);
+    }
+}
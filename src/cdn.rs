@@ -0,0 +1,152 @@
+use {
+    crate::{
+        defaults,
+        errors::{Result, ResultExt},
+        logic,
+    },
+    failure::bail,
+    std::net::IpAddr,
+};
+
+/// Classifies IPs and hostnames as served by a CDN or a cloud WAF
+pub struct CdnDetector {
+    ranges: Vec<(String, IpAddr, u8)>,
+    cnames: Vec<(String, String)>,
+}
+
+impl CdnDetector {
+    /// Bundled lists plus the ranges of `ranges_file`, one `<provider> <cidr>`
+    /// (or `<provider>,<cidr>`) per line. The ranges of the file are checked first
+    pub fn new(ranges_file: Option<&str>) -> Result<Self> {
+        let mut ranges = Vec::new();
+        if let Some(path) = ranges_file {
+            let content = std::fs::read_to_string(path)
+                .with_context(|_| format!("Can't read the CDN ranges file {path}"))?;
+            for line in content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            {
+                let parsed = line
+                    .rsplit_once(|c: char| c == ',' || c.is_whitespace())
+                    .and_then(|(provider, cidr)| {
+                        logic::parse_cidr(cidr).map(|(network, prefix)| {
+                            (
+                                provider.trim_end_matches(',').trim().to_string(),
+                                network,
+                                prefix,
+                            )
+                        })
+                    });
+                match parsed {
+                    Some(range) => ranges.push(range),
+                    None => bail!(
+                        "Invalid line in {}: {}. The expected format is <provider> <cidr>",
+                        path,
+                        line
+                    ),
+                }
+            }
+        }
+        ranges.extend(defaults::CDN_RANGES.iter().filter_map(|(provider, cidr)| {
+            logic::parse_cidr(cidr).map(|(network, prefix)| (provider.to_string(), network, prefix))
+        }));
+
+        Ok(Self {
+            ranges,
+            cnames: defaults::CDN_CNAMES
+                .iter()
+                .map(|(provider, suffix)| (provider.to_string(), suffix.to_string()))
+                .collect(),
+        })
+    }
+
+    #[must_use]
+    pub fn by_ip(&self, ip: &str) -> Option<String> {
+        let ip: IpAddr = ip.parse().ok()?;
+        self.ranges
+            .iter()
+            .find(|(_, network, prefix)| logic::cidr_contains(*network, *prefix, ip))
+            .map(|(provider, _, _)| provider.clone())
+    }
+
+    #[must_use]
+    pub fn by_cnames(&self, cnames: &[String]) -> Option<String> {
+        cnames.iter().find_map(|cname| {
+            let cname = cname.trim_end_matches('.').to_lowercase();
+            self.cnames
+                .iter()
+                .find(|(_, suffix)| cname.ends_with(suffix.as_str()))
+                .map(|(provider, _)| provider.clone())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cnames(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| (*name).to_string()).collect()
+    }
+
+    #[test]
+    fn by_ip_with_the_default_ranges() {
+        let cdn = CdnDetector::new(None).unwrap();
+        assert_eq!(cdn.by_ip("104.16.132.229").as_deref(), Some("Cloudflare"));
+        assert_eq!(cdn.by_ip("151.101.1.69").as_deref(), Some("Fastly"));
+        assert_eq!(cdn.by_ip("23.235.32.0").as_deref(), Some("Fastly"));
+        assert_eq!(cdn.by_ip("23.235.47.255").as_deref(), Some("Fastly"));
+        assert_eq!(cdn.by_ip("23.235.48.0"), None);
+        assert_eq!(cdn.by_ip("45.60.12.1").as_deref(), Some("Imperva"));
+        assert_eq!(cdn.by_ip("23.45.67.89").as_deref(), Some("Akamai"));
+        assert_eq!(cdn.by_ip("8.8.8.8"), None);
+        assert_eq!(cdn.by_ip("www.example.com"), None);
+    }
+
+    #[test]
+    fn ranges_file_is_checked_first() {
+        let path =
+            std::env::temp_dir().join(format!("unimap-cdn-ranges-{}.txt", std::process::id()));
+        std::fs::write(
+            &path,
+            "# internal\nMy CDN, 104.16.0.0/24\nOther 10.0.0.0/8\n",
+        )
+        .unwrap();
+        let cdn = CdnDetector::new(path.to_str()).unwrap();
+        std::fs::write(&path, "My CDN 104.16.0.0/33\n").unwrap();
+        let invalid = CdnDetector::new(path.to_str());
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(cdn.by_ip("104.16.0.10").as_deref(), Some("My CDN"));
+        assert_eq!(cdn.by_ip("104.16.1.10").as_deref(), Some("Cloudflare"));
+        assert_eq!(cdn.by_ip("10.1.2.3").as_deref(), Some("Other"));
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn by_cnames_with_the_default_suffixes() {
+        let cdn = CdnDetector::new(None).unwrap();
+        assert_eq!(
+            cdn.by_cnames(&cnames(&["www.example.com.CDN.cloudflare.net."]))
+                .as_deref(),
+            Some("Cloudflare")
+        );
+        assert_eq!(
+            cdn.by_cnames(&cnames(&[
+                "shop.example.com",
+                "d111111abcdef8.cloudfront.net"
+            ]))
+            .as_deref(),
+            Some("CloudFront")
+        );
+        assert_eq!(
+            cdn.by_cnames(&cnames(&["e1234.a.akamaiedge.net", "x.fastly.net"]))
+                .as_deref(),
+            Some("Akamai")
+        );
+        assert_eq!(cdn.by_cnames(&cnames(&["fastly.net"])), None);
+        assert_eq!(cdn.by_cnames(&cnames(&["www.notcloudfront.net"])), None);
+        assert_eq!(cdn.by_cnames(&[]), None);
+    }
+}
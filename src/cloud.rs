@@ -0,0 +1,249 @@
+use {
+    crate::{
+        errors::{Result, ResultExt},
+        logic,
+    },
+    failure::bail,
+    serde_json::Value,
+    std::net::IpAddr,
+};
+
+/// Cloud provider an IP belongs to, according to its published IP ranges
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct CloudInfo {
+    pub provider: String,
+    pub region: Option<String>,
+    pub service: Option<String>,
+}
+
+struct CloudRange {
+    network: IpAddr,
+    prefix: u8,
+    info: CloudInfo,
+    /// Ranges that cover the whole provider, e.g. the AMAZON service of AWS
+    generic: bool,
+}
+
+/// IP ranges loaded from the JSON files published by AWS (ip-ranges.json),
+/// Google Cloud (cloud.json), Azure (ServiceTags_Public_*.json) and Oracle
+/// Cloud (public_ip_ranges.json)
+pub struct CloudRanges {
+    /// Ranges grouped by prefix length, the longest first, and sorted by network
+    /// inside each group so a lookup is a binary search per prefix length
+    ranges: Vec<(u8, Vec<CloudRange>)>,
+}
+
+impl CloudRanges {
+    pub fn load(paths: &[String]) -> Result<Self> {
+        let mut ranges = Vec::new();
+        for path in paths {
+            let content = std::fs::read_to_string(path)
+                .with_context(|_| format!("Can't read the cloud ranges file {path}"))?;
+            let json: Value = serde_json::from_str(&content)
+                .with_context(|_| format!("Can't parse the cloud ranges file {path}"))?;
+            let found = parse_ranges(&json);
+            if found.is_empty() {
+                bail!(
+                    "No IP ranges found in {}, only the AWS, Google Cloud, Azure and Oracle Cloud files are supported",
+                    path
+                )
+            }
+            ranges.extend(found);
+        }
+        // The most specific range wins, so regional services are preferred
+        // over the ranges that cover the whole provider
+        ranges.sort_by(|a, b| {
+            b.prefix
+                .cmp(&a.prefix)
+                .then_with(|| a.network.cmp(&b.network))
+                .then_with(|| a.generic.cmp(&b.generic))
+        });
+        let mut groups: Vec<(u8, Vec<CloudRange>)> = Vec::new();
+        for range in ranges {
+            match groups.last_mut() {
+                Some((prefix, group)) if *prefix == range.prefix => group.push(range),
+                _ => groups.push((range.prefix, vec![range])),
+            }
+        }
+        Ok(Self { ranges: groups })
+    }
+
+    #[must_use]
+    pub fn lookup(&self, ip: &str) -> Option<CloudInfo> {
+        let ip: IpAddr = ip.parse().ok()?;
+        self.ranges.iter().find_map(|(prefix, ranges)| {
+            let network = logic::network_address(ip, *prefix);
+            let position = ranges.partition_point(|range| range.network < network);
+            ranges
+                .get(position)
+                .filter(|range| range.network == network)
+                .map(|range| range.info.clone())
+        })
+    }
+}
+
+fn parse_ranges(json: &Value) -> Vec<CloudRange> {
+    // Both AWS and Google Cloud use "prefixes", only AWS has "ipv6_prefixes"
+    if json.get("ipv6_prefixes").is_some() {
+        aws_ranges(json)
+    } else if json.get("prefixes").is_some() {
+        gcp_ranges(json)
+    } else if json.get("values").is_some() {
+        azure_ranges(json)
+    } else if json.get("regions").is_some() {
+        oracle_ranges(json)
+    } else {
+        Vec::new()
+    }
+}
+
+fn text(value: &Value, field: &str) -> Option<String> {
+    value
+        .get(field)
+        .and_then(Value::as_str)
+        .filter(|text| !text.is_empty())
+        .map(str::to_string)
+}
+
+fn array<'a>(value: &'a Value, field: &str) -> impl Iterator<Item = &'a Value> {
+    value
+        .get(field)
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+}
+
+fn new_range(cidr: &str, info: CloudInfo, generic: bool) -> Option<CloudRange> {
+    logic::parse_cidr(cidr).map(|(network, prefix)| CloudRange {
+        network: logic::network_address(network, prefix),
+        prefix,
+        info,
+        generic,
+    })
+}
+
+/// {"prefixes": [{"ip_prefix", "region", "service"}], "ipv6_prefixes": [{"ipv6_prefix", ...}]}
+fn aws_ranges(json: &Value) -> Vec<CloudRange> {
+    array(json, "prefixes")
+        .chain(array(json, "ipv6_prefixes"))
+        .filter_map(|prefix| {
+            let cidr = text(prefix, "ip_prefix").or_else(|| text(prefix, "ipv6_prefix"))?;
+            let service = text(prefix, "service");
+            let generic = service.as_deref() == Some("AMAZON");
+            let info = CloudInfo {
+                provider: String::from("AWS"),
+                region: text(prefix, "region").filter(|region| region != "GLOBAL"),
+                service,
+            };
+            new_range(&cidr, info, generic)
+        })
+        .collect()
+}
+
+/// {"prefixes": [{"ipv4Prefix" or "ipv6Prefix", "service", "scope"}]}
+fn gcp_ranges(json: &Value) -> Vec<CloudRange> {
+    array(json, "prefixes")
+        .filter_map(|prefix| {
+            let cidr = text(prefix, "ipv4Prefix").or_else(|| text(prefix, "ipv6Prefix"))?;
+            let info = CloudInfo {
+                provider: String::from("GCP"),
+                region: text(prefix, "scope").filter(|scope| scope != "global"),
+                service: text(prefix, "service"),
+            };
+            new_range(&cidr, info, false)
+        })
+        .collect()
+}
+
+/// {"values": [{"name", "properties": {"region", "systemService", "addressPrefixes": []}}]}
+fn azure_ranges(json: &Value) -> Vec<CloudRange> {
+    array(json, "values")
+        .flat_map(|tag| {
+            let properties = tag.get("properties").cloned().unwrap_or_default();
+            let service = text(&properties, "systemService");
+            let generic = service.is_none();
+            let info = CloudInfo {
+                provider: String::from("Azure"),
+                region: text(&properties, "region"),
+                service: service.or_else(|| text(tag, "name")),
+            };
+            array(&properties, "addressPrefixes")
+                .filter_map(Value::as_str)
+                .filter_map(|cidr| new_range(cidr, info.clone(), generic))
+                .collect::<Vec<CloudRange>>()
+        })
+        .collect()
+}
+
+/// {"regions": [{"region", "cidrs": [{"cidr", "tags": []}]}]}
+fn oracle_ranges(json: &Value) -> Vec<CloudRange> {
+    array(json, "regions")
+        .flat_map(|region| {
+            let name = text(region, "region");
+            array(region, "cidrs")
+                .filter_map(|cidr| {
+                    let info = CloudInfo {
+                        provider: String::from("Oracle"),
+                        region: name.clone(),
+                        service: cidr
+                            .get("tags")
+                            .and_then(Value::as_array)
+                            .map(|tags| {
+                                tags.iter()
+                                    .filter_map(Value::as_str)
+                                    .collect::<Vec<&str>>()
+                                    .join(" ")
+                            })
+                            .filter(|tags| !tags.is_empty()),
+                    };
+                    new_range(&text(cidr, "cidr")?, info, false)
+                })
+                .collect::<Vec<CloudRange>>()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_prefers_the_most_specific_range() {
+        let path = std::env::temp_dir().join(format!("unimap-cloud-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{"prefixes": [
+                {"ip_prefix": "3.0.0.0/8", "region": "GLOBAL", "service": "AMAZON"},
+                {"ip_prefix": "3.5.0.0/16", "region": "us-east-1", "service": "AMAZON"},
+                {"ip_prefix": "3.5.0.0/16", "region": "us-east-1", "service": "S3"},
+                {"ip_prefix": "3.5.140.0/22", "region": "ap-northeast-2", "service": "EC2"}
+            ], "ipv6_prefixes": [
+                {"ipv6_prefix": "2600:1f00::/24", "region": "us-west-2", "service": "EC2"}
+            ]}"#,
+        )
+        .unwrap();
+        let ranges = CloudRanges::load(&[path.display().to_string()]).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let region = |ip: &str| {
+            ranges
+                .lookup(ip)
+                .map(|info| (info.region, info.service.unwrap_or_default()))
+        };
+        assert_eq!(
+            region("3.5.141.7"),
+            Some((Some(String::from("ap-northeast-2")), String::from("EC2")))
+        );
+        assert_eq!(
+            region("3.5.1.1"),
+            Some((Some(String::from("us-east-1")), String::from("S3")))
+        );
+        assert_eq!(region("3.200.1.1"), Some((None, String::from("AMAZON"))));
+        assert_eq!(
+            region("2600:1f00::1"),
+            Some((Some(String::from("us-west-2")), String::from("EC2")))
+        );
+        assert_eq!(region("4.1.1.1"), None);
+        assert_eq!(region("2a00::1"), None);
+    }
+}
@@ -21,3 +21,106 @@ pub fn ipv4_resolvers() -> Vec<String> {
     .map(str::to_owned)
     .collect()
 }
+
+/// Ports scanned by the built-in connect scanner when --ports is not used,
+/// the same list used by Nmap -F (top 100 TCP ports)
+pub const TOP_PORTS: &str = "7,9,13,21-23,25-26,37,53,79-81,88,106,110-111,113,119,135,139,143-144,179,199,389,427,443-445,465,513-515,543-544,548,554,587,631,646,873,990,993,995,1025-1029,1110,1433,1720,1723,1755,1900,2000-2001,2049,2121,2717,3000,3128,3306,3389,3986,4899,5000,5009,5051,5060,5101,5190,5357,5432,5631,5666,5800,5900,6000-6001,6646,7070,8000,8008-8009,8080-8081,8443,8888,9100,9999-10000,32768,49152-49157";
+
+/// Ports scanned on CDN edge IPs with `--cdn web-ports`
+pub const CDN_WEB_PORTS: &str = "80,443,8080,8443";
+
+/// IPv4 ranges of the major CDNs and cloud WAFs, taken from the lists each
+/// provider publishes. Akamai doesn't publish one, those are its best known
+/// ranges. Use --cdn-ranges to add newer or missing ones
+pub const CDN_RANGES: &[(&str, &str)] = &[
+    // https://www.cloudflare.com/ips-v4
+    ("Cloudflare", "173.245.48.0/20"),
+    ("Cloudflare", "103.21.244.0/22"),
+    ("Cloudflare", "103.22.200.0/22"),
+    ("Cloudflare", "103.31.4.0/22"),
+    ("Cloudflare", "141.101.64.0/18"),
+    ("Cloudflare", "108.162.192.0/18"),
+    ("Cloudflare", "190.93.240.0/20"),
+    ("Cloudflare", "188.114.96.0/20"),
+    ("Cloudflare", "197.234.240.0/22"),
+    ("Cloudflare", "198.41.128.0/17"),
+    ("Cloudflare", "162.158.0.0/15"),
+    ("Cloudflare", "104.16.0.0/13"),
+    ("Cloudflare", "104.24.0.0/14"),
+    ("Cloudflare", "172.64.0.0/13"),
+    ("Cloudflare", "131.0.72.0/22"),
+    // https://api.fastly.com/public-ip-list
+    ("Fastly", "23.235.32.0/20"),
+    ("Fastly", "43.249.72.0/22"),
+    ("Fastly", "103.244.50.0/24"),
+    ("Fastly", "103.245.222.0/23"),
+    ("Fastly", "103.245.224.0/24"),
+    ("Fastly", "104.156.80.0/20"),
+    ("Fastly", "140.248.64.0/18"),
+    ("Fastly", "140.248.128.0/17"),
+    ("Fastly", "146.75.0.0/17"),
+    ("Fastly", "151.101.0.0/16"),
+    ("Fastly", "157.52.64.0/18"),
+    ("Fastly", "167.82.0.0/17"),
+    ("Fastly", "167.82.128.0/20"),
+    ("Fastly", "167.82.160.0/20"),
+    ("Fastly", "167.82.224.0/20"),
+    ("Fastly", "172.111.64.0/18"),
+    ("Fastly", "185.31.16.0/22"),
+    ("Fastly", "199.27.72.0/21"),
+    ("Fastly", "199.232.0.0/16"),
+    // Imperva Incapsula
+    ("Imperva", "199.83.128.0/21"),
+    ("Imperva", "198.143.32.0/19"),
+    ("Imperva", "149.126.72.0/21"),
+    ("Imperva", "103.28.248.0/22"),
+    ("Imperva", "45.64.64.0/22"),
+    ("Imperva", "185.11.124.0/22"),
+    ("Imperva", "192.230.64.0/18"),
+    ("Imperva", "107.154.0.0/16"),
+    ("Imperva", "45.60.0.0/16"),
+    ("Imperva", "45.223.0.0/16"),
+    ("Imperva", "131.125.128.0/17"),
+    // Sucuri firewall
+    ("Sucuri", "192.88.134.0/23"),
+    ("Sucuri", "185.93.228.0/22"),
+    ("Sucuri", "66.248.200.0/22"),
+    ("Sucuri", "208.109.0.0/22"),
+    // Akamai
+    ("Akamai", "2.16.0.0/13"),
+    ("Akamai", "23.0.0.0/12"),
+    ("Akamai", "23.32.0.0/11"),
+    ("Akamai", "23.64.0.0/14"),
+    ("Akamai", "23.72.0.0/13"),
+    ("Akamai", "23.192.0.0/11"),
+    ("Akamai", "72.246.0.0/15"),
+    ("Akamai", "88.221.0.0/16"),
+    ("Akamai", "92.122.0.0/15"),
+    ("Akamai", "95.100.0.0/15"),
+    ("Akamai", "96.6.0.0/15"),
+    ("Akamai", "104.64.0.0/10"),
+    ("Akamai", "173.222.0.0/15"),
+    ("Akamai", "184.24.0.0/13"),
+    ("Akamai", "184.50.0.0/15"),
+    ("Akamai", "184.84.0.0/14"),
+];
+
+/// CNAME suffixes of the hostnames served by a CDN or cloud WAF
+pub const CDN_CNAMES: &[(&str, &str)] = &[
+    ("Cloudflare", ".cdn.cloudflare.net"),
+    ("Fastly", ".fastly.net"),
+    ("Fastly", ".fastlylb.net"),
+    ("Akamai", ".akamai.net"),
+    ("Akamai", ".akamaiedge.net"),
+    ("Akamai", ".akamaized.net"),
+    ("Akamai", ".edgekey.net"),
+    ("Akamai", ".edgesuite.net"),
+    ("CloudFront", ".cloudfront.net"),
+    ("Imperva", ".incapdns.net"),
+    ("Imperva", ".impervadns.net"),
+    ("Sucuri", ".sucuri.net"),
+    ("Azure Front Door", ".azurefd.net"),
+    ("Azure CDN", ".azureedge.net"),
+    ("StackPath", ".stackpathdns.com"),
+    ("Google Cloud CDN", ".googlehosted.com"),
+];
@@ -0,0 +1,569 @@
+use {
+    crate::{
+        args::DiffArgs,
+        errors::{Result, ResultExt},
+        output::{sqlite, HostResult, PortResult, Report, ServiceResult, SCHEMA_VERSION},
+    },
+    chrono::{DateTime, Utc},
+    failure::bail,
+    log::info,
+    prettytable::Table,
+    std::{
+        collections::{BTreeMap, BTreeSet, HashMap},
+        io::Write,
+    },
+};
+
+/// Changes between an old and a new run, hosts are compared by name
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Diff {
+    pub old_generated_at: String,
+    pub new_generated_at: String,
+    pub new_hosts: Vec<HostChange>,
+    pub removed_hosts: Vec<HostChange>,
+    pub ip_changes: Vec<IpChange>,
+    pub opened_ports: Vec<PortChange>,
+    pub closed_ports: Vec<PortChange>,
+    pub service_changes: Vec<ServiceChange>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct HostChange {
+    pub host: String,
+    pub ip: Option<String>,
+    pub ports: Vec<u16>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct IpChange {
+    pub host: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PortChange {
+    pub host: String,
+    pub ip: Option<String>,
+    pub port: u16,
+    pub protocol: String,
+    pub service: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ServiceChange {
+    pub host: String,
+    pub ip: Option<String>,
+    pub port: u16,
+    pub protocol: String,
+    pub old: String,
+    pub new: String,
+}
+
+/// A line of `--jsonl port`, the port fields are null for the hosts without
+/// open ports
+#[derive(Deserialize)]
+struct PortLine {
+    host: String,
+    ip: Option<String>,
+    scan_status: String,
+    port: Option<u16>,
+    protocol: Option<String>,
+    state: Option<String>,
+    reason: Option<String>,
+    service: Option<ServiceResult>,
+}
+
+impl Diff {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.new_hosts.is_empty()
+            && self.removed_hosts.is_empty()
+            && self.ip_changes.is_empty()
+            && self.opened_ports.is_empty()
+            && self.closed_ports.is_empty()
+            && self.service_changes.is_empty()
+    }
+}
+
+/// The `diff` command
+pub fn run(args: &DiffArgs) -> Result<()> {
+    let new_run = match args.new_run {
+        None if sqlite::is_database(&args.new) => Some(sqlite::latest_run(&args.new, None)?),
+        run => run,
+    };
+    // With the same database on both sides, compare its last two runs
+    let old_run = match args.old_run {
+        None if sqlite::is_database(&args.old) => {
+            let before = new_run.filter(|_| args.old == args.new);
+            Some(sqlite::latest_run(&args.old, before)?)
+        }
+        run => run,
+    };
+    let old = load(&args.old, old_run)?;
+    let new = load(&args.new, new_run)?;
+    print_diff(&compare(&old, &new), args.json)
+}
+
+/// Load the results of a run from a --json or --jsonl (host or port) file,
+/// or from a SQLite database written with --sqlite (its latest run unless
+/// `run` is given)
+pub fn load(path: &str, run: Option<i64>) -> Result<Report> {
+    if sqlite::is_database(path) {
+        let run = match run {
+            Some(run) => run,
+            None => sqlite::latest_run(path, None)?,
+        };
+        return sqlite::read_sqlite(path, run);
+    }
+
+    let content = std::fs::read_to_string(path)
+        .with_context(|_| format!("Can't read the results file {path}"))?;
+    if let Ok(report) = serde_json::from_str::<Report>(&content) {
+        return Ok(report);
+    }
+    match parse_jsonl(&content) {
+        // JSON Lines have no header, the file date is the closest thing
+        Some(hosts) => Ok(Report {
+            schema_version: SCHEMA_VERSION,
+            unimap_version: String::new(),
+            generated_at: std::fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .map(|modified| DateTime::<Utc>::from(modified).to_rfc3339())
+                .unwrap_or_default(),
+            hosts,
+        }),
+        None => bail!(
+            "Can't read the results of {}, only the --json, --jsonl and --sqlite outputs are supported",
+            path
+        ),
+    }
+}
+
+/// Hosts of a `--jsonl host` file, or of a `--jsonl port` file with the
+/// lines of every host grouped back. Port lines only have the host, IP,
+/// scan status and ports, so the other host fields are empty
+fn parse_jsonl(content: &str) -> Option<Vec<HostResult>> {
+    let lines: Vec<&str> = content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+    if lines.is_empty() {
+        return None;
+    }
+    if let Ok(hosts) = lines
+        .iter()
+        .map(|line| serde_json::from_str::<HostResult>(line))
+        .collect()
+    {
+        return Some(hosts);
+    }
+
+    let mut hosts: Vec<HostResult> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for line in lines {
+        let line: PortLine = serde_json::from_str(line).ok()?;
+        let position = match positions.get(&line.host) {
+            Some(position) => *position,
+            None => {
+                positions.insert(line.host.clone(), hosts.len());
+                hosts.push(HostResult {
+                    host: line.host,
+                    ips: line.ip.iter().cloned().collect(),
+                    ip: line.ip,
+                    scan_status: line.scan_status,
+                    ..HostResult::default()
+                });
+                hosts.len() - 1
+            }
+        };
+        if let Some(port) = line.port {
+            hosts[position].ports.push(PortResult {
+                port,
+                protocol: line.protocol.unwrap_or_default(),
+                state: line.state.unwrap_or_default(),
+                reason: line.reason.unwrap_or_default(),
+                service: line.service,
+            });
+        }
+    }
+    Some(hosts)
+}
+
+#[must_use]
+pub fn compare(old: &Report, new: &Report) -> Diff {
+    let old_hosts: BTreeMap<&str, &HostResult> = old
+        .hosts
+        .iter()
+        .map(|host| (host.host.as_str(), host))
+        .collect();
+    let new_hosts: BTreeMap<&str, &HostResult> = new
+        .hosts
+        .iter()
+        .map(|host| (host.host.as_str(), host))
+        .collect();
+    let mut diff = Diff {
+        old_generated_at: old.generated_at.clone(),
+        new_generated_at: new.generated_at.clone(),
+        ..Diff::default()
+    };
+
+    for (name, host) in &new_hosts {
+        if !old_hosts.contains_key(name) {
+            diff.new_hosts.push(host_change(host));
+        }
+    }
+    for (name, host) in &old_hosts {
+        if !new_hosts.contains_key(name) {
+            diff.removed_hosts.push(host_change(host));
+        }
+    }
+
+    for (name, new_host) in &new_hosts {
+        let old_host = match old_hosts.get(name) {
+            Some(old_host) => old_host,
+            None => continue,
+        };
+        if old_host.ip != new_host.ip {
+            diff.ip_changes.push(IpChange {
+                host: name.to_string(),
+                old: old_host.ip.clone(),
+                new: new_host.ip.clone(),
+            });
+        }
+        // A failed or skipped scan says nothing about the ports
+        if !scanned(old_host) || !scanned(new_host) {
+            continue;
+        }
+
+        let old_ports: BTreeMap<(u16, &str), &PortResult> = ports_by_key(old_host);
+        let new_ports: BTreeMap<(u16, &str), &PortResult> = ports_by_key(new_host);
+        let keys: BTreeSet<&(u16, &str)> = old_ports.keys().chain(new_ports.keys()).collect();
+        for key in keys {
+            match (old_ports.get(key), new_ports.get(key)) {
+                (None, Some(port)) => diff.opened_ports.push(port_change(new_host, port)),
+                (Some(port), None) => diff.closed_ports.push(port_change(old_host, port)),
+                (Some(old_port), Some(new_port)) => {
+                    let (old_service, new_service) =
+                        (service_string(old_port), service_string(new_port));
+                    if let (Some(old_service), Some(new_service)) = (old_service, new_service) {
+                        if old_service != new_service {
+                            diff.service_changes.push(ServiceChange {
+                                host: name.to_string(),
+                                ip: new_host.ip.clone(),
+                                port: new_port.port,
+                                protocol: new_port.protocol.clone(),
+                                old: old_service,
+                                new: new_service,
+                            });
+                        }
+                    }
+                }
+                (None, None) => (),
+            }
+        }
+    }
+    diff
+}
+
+/// Print the changes as a table, or as JSON
+pub fn print_diff(diff: &Diff, json: bool) -> Result<()> {
+    if !json && diff.is_empty() {
+        info!("No changes since {}\n", diff.old_generated_at);
+        return Ok(());
+    }
+    write_diff(diff, json, std::io::stdout().lock())
+}
+
+fn write_diff<W: Write>(diff: &Diff, json: bool, mut writer: W) -> Result<()> {
+    if json {
+        serde_json::to_writer_pretty(&mut writer, diff)?;
+        writeln!(writer)?;
+        return Ok(());
+    }
+
+    let mut table = Table::new();
+    table.set_titles(row![
+        bcFg => "CHANGE",
+       "HOST",
+       "IP",
+       "PORT",
+       "DETAILS"
+    ]);
+    for (change, hosts) in [
+        ("NEW HOST", &diff.new_hosts),
+        ("REMOVED HOST", &diff.removed_hosts),
+    ] {
+        for host in hosts {
+            let ports: Vec<String> = host.ports.iter().map(u16::to_string).collect();
+            table.add_row(row![
+                change,
+                host.host,
+                host.ip.as_deref().unwrap_or("NULL"),
+                ports.join(", "),
+                ""
+            ]);
+        }
+    }
+    for change in &diff.ip_changes {
+        table.add_row(row![
+            "IP CHANGED",
+            change.host,
+            change.new.as_deref().unwrap_or("NULL"),
+            "",
+            format!("was {}", change.old.as_deref().unwrap_or("NULL"))
+        ]);
+    }
+    for (change, ports) in [
+        ("PORT OPENED", &diff.opened_ports),
+        ("PORT CLOSED", &diff.closed_ports),
+    ] {
+        for port in ports {
+            table.add_row(row![
+                change,
+                port.host,
+                port.ip.as_deref().unwrap_or("NULL"),
+                format!("{}/{}", port.port, port.protocol),
+                port.service.as_deref().unwrap_or("")
+            ]);
+        }
+    }
+    for change in &diff.service_changes {
+        table.add_row(row![
+            "SERVICE CHANGED",
+            change.host,
+            change.ip.as_deref().unwrap_or("NULL"),
+            format!("{}/{}", change.port, change.protocol),
+            format!("{} => {}", change.old, change.new)
+        ]);
+    }
+    table.print(&mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn scanned(host: &HostResult) -> bool {
+    host.scan_status == "ok"
+}
+
+fn ports_by_key(host: &HostResult) -> BTreeMap<(u16, &str), &PortResult> {
+    host.ports
+        .iter()
+        .map(|port| ((port.port, port.protocol.as_str()), port))
+        .collect()
+}
+
+fn host_change(host: &HostResult) -> HostChange {
+    HostChange {
+        host: host.host.clone(),
+        ip: host.ip.clone(),
+        ports: host.ports.iter().map(|port| port.port).collect(),
+    }
+}
+
+fn port_change(host: &HostResult, port: &PortResult) -> PortChange {
+    PortChange {
+        host: host.host.clone(),
+        ip: host.ip.clone(),
+        port: port.port,
+        protocol: port.protocol.clone(),
+        service: service_string(port),
+    }
+}
+
+/// Service name and description, e.g. `ssh OpenSSH 8.9p1`
+fn service_string(port: &PortResult) -> Option<String> {
+    port.service.as_ref().map(|service| {
+        let description = service.description();
+        if description.is_empty() {
+            service.name.clone()
+        } else {
+            format!("{} {description}", service.name)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            args::JsonlMode,
+            output::{json::write_jsonl, sample_report},
+        },
+        serde_json::json,
+    };
+
+    fn host(name: &str, ip: &str, ports: &[u16]) -> HostResult {
+        HostResult {
+            host: name.to_string(),
+            ip: Some(ip.to_string()),
+            ips: vec![ip.to_string()],
+            scan_status: String::from("ok"),
+            ports: ports
+                .iter()
+                .map(|port| PortResult {
+                    port: *port,
+                    protocol: String::from("tcp"),
+                    state: String::from("open"),
+                    reason: String::from("syn-ack"),
+                    service: None,
+                })
+                .collect(),
+            ..HostResult::default()
+        }
+    }
+
+    fn reports() -> (Report, Report) {
+        let mut old = sample_report();
+        old.hosts.push(host("gone.example.com", "192.0.2.9", &[80]));
+        old.hosts
+            .push(host("moved.example.com", "192.0.2.1", &[443]));
+        old.hosts
+            .push(host("failed.example.com", "192.0.2.5", &[22, 80]));
+
+        let mut new = sample_report();
+        new.generated_at = String::from("2024-02-01T00:00:00+00:00");
+        let www = &mut new.hosts[1];
+        www.ports[0].service.as_mut().unwrap().version = Some(String::from("9.6p1"));
+        www.ports.pop();
+        let mut mysql = www.ports[0].clone();
+        mysql.port = 3306;
+        mysql.service = Some(ServiceResult {
+            name: String::from("mysql"),
+            product: Some(String::from("MySQL")),
+            version: Some(String::from("8.0.36")),
+            ..ServiceResult::default()
+        });
+        www.ports.push(mysql);
+        new.hosts
+            .push(host("fresh.example.com", "192.0.2.7", &[80, 443]));
+        new.hosts
+            .push(host("moved.example.com", "192.0.2.2", &[443]));
+        let mut failed = host("failed.example.com", "192.0.2.5", &[]);
+        failed.scan_status = String::from("timeout");
+        new.hosts.push(failed);
+        (old, new)
+    }
+
+    #[test]
+    fn compare_runs() {
+        let (old, new) = reports();
+        let diff = compare(&old, &new);
+
+        assert_eq!(
+            serde_json::to_value(&diff).unwrap(),
+            json!({
+                "old_generated_at": "2024-01-01T00:00:00+00:00",
+                "new_generated_at": "2024-02-01T00:00:00+00:00",
+                "new_hosts": [{"host": "fresh.example.com", "ip": "192.0.2.7", "ports": [80, 443]}],
+                "removed_hosts": [{"host": "gone.example.com", "ip": "192.0.2.9", "ports": [80]}],
+                "ip_changes": [{"host": "moved.example.com", "old": "192.0.2.1", "new": "192.0.2.2"}],
+                "opened_ports": [{"host": "www.example.com", "ip": "93.184.216.34", "port": 3306,
+                                  "protocol": "tcp", "service": "mysql MySQL 8.0.36"}],
+                "closed_ports": [{"host": "www.example.com", "ip": "93.184.216.34", "port": 8080,
+                                  "protocol": "tcp", "service": null}],
+                "service_changes": [{"host": "www.example.com", "ip": "93.184.216.34", "port": 22,
+                                     "protocol": "tcp", "old": "ssh OpenSSH 8.9p1", "new": "ssh OpenSSH 9.6p1"}]
+            })
+        );
+        assert!(compare(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn diff_outputs() {
+        let (old, new) = reports();
+        let diff = compare(&old, &new);
+
+        let mut output = Vec::new();
+        write_diff(&diff, true, &mut output).unwrap();
+        let printed: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(printed, serde_json::to_value(&diff).unwrap());
+
+        let mut output = Vec::new();
+        write_diff(&diff, false, &mut output).unwrap();
+        let table = String::from_utf8(output).unwrap();
+        let rows: Vec<Vec<&str>> = table
+            .lines()
+            .filter(|line| line.starts_with('|'))
+            .map(|line| line.split('|').map(str::trim).skip(1).take(5).collect())
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                vec!["CHANGE", "HOST", "IP", "PORT", "DETAILS"],
+                vec!["NEW HOST", "fresh.example.com", "192.0.2.7", "80, 443", ""],
+                vec!["REMOVED HOST", "gone.example.com", "192.0.2.9", "80", ""],
+                vec![
+                    "IP CHANGED",
+                    "moved.example.com",
+                    "192.0.2.2",
+                    "",
+                    "was 192.0.2.1"
+                ],
+                vec![
+                    "PORT OPENED",
+                    "www.example.com",
+                    "93.184.216.34",
+                    "3306/tcp",
+                    "mysql MySQL 8.0.36"
+                ],
+                vec![
+                    "PORT CLOSED",
+                    "www.example.com",
+                    "93.184.216.34",
+                    "8080/tcp",
+                    ""
+                ],
+                vec![
+                    "SERVICE CHANGED",
+                    "www.example.com",
+                    "93.184.216.34",
+                    "22/tcp",
+                    "ssh OpenSSH 8.9p1 => ssh OpenSSH 9.6p1"
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn load_jsonl_host_and_port_files() {
+        let (old, new) = reports();
+        let path = std::env::temp_dir().join(format!("unimap-diff-{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut output = Vec::new();
+        write_jsonl(&old, JsonlMode::Host, &mut output).unwrap();
+        std::fs::write(path, &output).unwrap();
+        let hosts = load(path, None).map(|report| report.hosts);
+
+        let mut output = Vec::new();
+        write_jsonl(&new, JsonlMode::Port, &mut output).unwrap();
+        std::fs::write(path, &output).unwrap();
+        let ports = load(path, None);
+
+        std::fs::write(path, "{\"host\": \"www.example.com\"}\n").unwrap();
+        let invalid = load(path, None);
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(hosts.unwrap(), old.hosts);
+        let ports = ports.unwrap();
+        let expected: Vec<HostResult> = new
+            .hosts
+            .iter()
+            .map(|host| HostResult {
+                host: host.host.clone(),
+                ip: host.ip.clone(),
+                ips: host.ip.iter().cloned().collect(),
+                scan_status: host.scan_status.clone(),
+                ports: host.ports.clone(),
+                ..HostResult::default()
+            })
+            .collect();
+        assert_eq!(ports.hosts, expected);
+        assert_eq!(ports.hosts[2].ports.len(), 2);
+        assert!(ports.hosts[4].ports.is_empty());
+        assert_eq!(compare(&old, &ports).service_changes.len(), 1);
+        assert!(invalid.is_err());
+    }
+}
@@ -1,14 +1,15 @@
 use {
     crate::{
-        args::ProcessedArgs,
+        args::{OutputFormat, ProcessedArgs},
         errors::{Result, ResultExt},
+        output::{self, Report},
     },
+    chrono::Utc,
     log::error,
-    prettytable::Table,
     std::{
         collections::HashSet,
         fs::{self, File, OpenOptions},
-        io::{BufRead, BufReader, Write},
+        io::{BufRead, BufReader, BufWriter, Write},
         path::Path,
     },
 };
@@ -43,25 +44,48 @@ pub fn return_file_targets(args: &ProcessedArgs, mut files: Vec<String>) -> Vec<
     targets.iter().map(|target| target.to_lowercase()).collect()
 }
 
-pub fn table_to_file(table: &Table, file_name: Option<std::fs::File>) -> Result<()> {
-    table.to_csv(file_name.unwrap())?;
-    Ok(())
+/// Write one of the outputs of the run to `path`
+pub fn output_to_file(
+    args: &ProcessedArgs,
+    report: &Report,
+    format: OutputFormat,
+    path: &str,
+) -> Result<()> {
+    match format {
+        OutputFormat::Csv => csv_to_file(args, report, path),
+        OutputFormat::Html => html_to_file(report, path),
+        OutputFormat::NmapXml => output::xml::write_nmap_xml(report, create_output_file(path)?),
+        OutputFormat::Sqlite => {
+            let elapsed =
+                chrono::Duration::from_std(args.time_wasted.elapsed()).unwrap_or_default();
+            let started_at = (Utc::now() - elapsed).to_rfc3339();
+            output::sqlite::write_sqlite(report, path, &started_at).map(|_| ())
+        }
+        _ => output::write_report(report, format, create_output_file(path)?),
+    }
 }
 
-#[must_use]
-pub fn return_output_file(args: &ProcessedArgs) -> Option<File> {
-    if args.file_name.is_empty() || !args.with_output {
-        None
-    } else {
-        Some(
-            OpenOptions::new()
-                .append(true)
-                .create(true)
-                .open(&args.file_name)
-                .with_context(|_| format!("Can't create file {}", &args.file_name))
-                .unwrap(),
-        )
-    }
+fn create_output_file(path: &str) -> Result<BufWriter<File>> {
+    let file = File::create(path).with_context(|_| format!("Can't create file {path}"))?;
+    Ok(BufWriter::new(file))
+}
+
+/// Append the CSV rows of the run to the output file, the header is only
+/// written when the file is new or empty
+fn csv_to_file(args: &ProcessedArgs, report: &Report, path: &str) -> Result<()> {
+    let file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .with_context(|_| format!("Can't create file {path}"))?;
+    let header = args.csv_header && file.metadata()?.len() == 0;
+    output::csv::write_csv(report, &args.csv_columns, args.csv_delimiter, header, file)
+}
+
+/// Write the HTML report, linking the Nmap XML files when they are kept
+fn html_to_file(report: &Report, path: &str) -> Result<()> {
+    let links = output::html::xml_links(report, path);
+    output::html::write_html(report, &links, create_output_file(path)?)
 }
 
 #[must_use]
@@ -0,0 +1,409 @@
+use {
+    crate::{
+        misc, networking,
+        nmap::{Port, Service},
+        structs::ResolvData,
+    },
+    futures::future::join_all,
+    std::{convert::TryFrom, net::Ipv4Addr, time::Duration},
+    tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+        time::timeout,
+    },
+};
+
+/// Value of `Service.method` for the services identified by unimap instead of Nmap
+pub const FINGERPRINT_METHOD: &str = "banner";
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+const READ_TIMEOUT: Duration = Duration::from_secs(3);
+const MAX_BANNER_LEN: usize = 4096;
+
+const HTTP_PROBE: &[u8] = b"GET / HTTP/1.0\r\nUser-Agent: unimap\r\nAccept: */*\r\n\r\n";
+const REDIS_PROBE: &[u8] = b"PING\r\n";
+
+const FTP_PRODUCTS: &[&str] = &[
+    "vsFTPd",
+    "ProFTPD",
+    "Pure-FTPd",
+    "FileZilla Server",
+    "Microsoft FTP Service",
+];
+const SMTP_PRODUCTS: &[&str] = &[
+    "Postfix",
+    "Exim",
+    "Sendmail",
+    "Microsoft ESMTP MAIL Service",
+    "OpenSMTPD",
+];
+
+/// Connect to every open port without a service detected by Nmap and try to
+/// identify it from its banner or from the answer to a few common probes
+pub fn fingerprint_services(scan_data: &mut ResolvData) {
+    let ip = match scan_data.ip.parse::<Ipv4Addr>() {
+        Ok(ip) => ip,
+        Err(_) => return,
+    };
+    let ports: Vec<&mut Port> = scan_data
+        .ports_data
+        .iter_mut()
+        .filter(|port| port.protocol == "tcp" && needs_fingerprint(port.service.as_ref()))
+        .collect();
+    if ports.is_empty() {
+        return;
+    }
+
+    networking::block_on(join_all(ports.into_iter().map(|port| async move {
+        if misc::interrupted() {
+            return;
+        }
+        if let Ok(portid) = port.portid.parse::<u16>() {
+            if let Some(found) = fingerprint_port(ip, portid).await {
+                merge_service(port, found);
+            }
+        }
+    })));
+}
+
+/// Nmap didn't probe the port: fast scans, discovery backends or a service
+/// name guessed from the port number (method "table")
+fn needs_fingerprint(service: Option<&Service>) -> bool {
+    service.is_none_or(|service| {
+        service.name.is_empty() || (service.method == "table" && service.product.is_none())
+    })
+}
+
+/// Fill only the fields that Nmap left empty
+fn merge_service(port: &mut Port, found: Service) {
+    match &mut port.service {
+        // Nothing identified: keep the service guessed by Nmap and only add the banner
+        Some(service) if found.name == "unknown" => {
+            service.extrainfo = service.extrainfo.take().or(found.extrainfo);
+        }
+        Some(service) => {
+            if service.name.is_empty() || service.method == "table" {
+                service.name = found.name;
+            }
+            service.product = service.product.take().or(found.product);
+            service.version = service.version.take().or(found.version);
+            service.extrainfo = service.extrainfo.take().or(found.extrainfo);
+            service.method = found.method;
+            service.conf = found.conf;
+        }
+        None => port.service = Some(found),
+    }
+}
+
+async fn fingerprint_port(ip: Ipv4Addr, port: u16) -> Option<Service> {
+    // Services that talk first: SSH, FTP, SMTP, etc.
+    if let Some(banner) = exchange(ip, port, None).await {
+        return Some(identify_banner(&banner));
+    }
+    if let Some(answer) = exchange(ip, port, Some(&client_hello())).await {
+        // A ServerHello (0x16) or an alert (0x15) means the port speaks TLS
+        if answer.len() > 2 && (answer[0] == 0x16 || answer[0] == 0x15) && answer[1] == 0x03 {
+            return Some(new_service("ssl", Some("TLS".to_string()), None, None));
+        }
+    }
+    if let Some(answer) = exchange(ip, port, Some(HTTP_PROBE)).await {
+        if answer.starts_with(b"HTTP/") {
+            return Some(identify_http(&String::from_utf8_lossy(&answer)));
+        }
+    }
+    if let Some(answer) = exchange(ip, port, Some(REDIS_PROBE)).await {
+        if answer.starts_with(b"+PONG") || answer.starts_with(b"-NOAUTH") {
+            return Some(new_service("redis", Some("Redis".to_string()), None, None));
+        }
+    }
+    None
+}
+
+/// Open a connection, optionally send a probe, and return what the service answers
+async fn exchange(ip: Ipv4Addr, port: u16, probe: Option<&[u8]>) -> Option<Vec<u8>> {
+    let mut stream = timeout(CONNECT_TIMEOUT, TcpStream::connect((ip, port)))
+        .await
+        .ok()?
+        .ok()?;
+    if let Some(probe) = probe {
+        stream.write_all(probe).await.ok()?;
+    }
+    let mut buffer = vec![0; MAX_BANNER_LEN];
+    let read = timeout(READ_TIMEOUT, stream.read(&mut buffer))
+        .await
+        .ok()?
+        .ok()?;
+    if read == 0 {
+        None
+    } else {
+        buffer.truncate(read);
+        Some(buffer)
+    }
+}
+
+fn new_service(
+    name: &str,
+    product: Option<String>,
+    version: Option<String>,
+    extrainfo: Option<String>,
+) -> Service {
+    Service {
+        name: name.to_string(),
+        method: FINGERPRINT_METHOD.to_string(),
+        conf: String::from("5"),
+        product,
+        ostype: None,
+        version,
+        extrainfo,
+        tunnel: None,
+    }
+}
+
+fn identify_banner(banner: &[u8]) -> Service {
+    let banner = String::from_utf8_lossy(banner);
+    let line = banner.lines().next().unwrap_or_default().trim();
+
+    if let Some(software) = line
+        .strip_prefix("SSH-")
+        .and_then(|rest| rest.split_once('-'))
+    {
+        // SSH-2.0-OpenSSH_9.6p1 Ubuntu-3ubuntu13
+        let (software, comments) = software
+            .1
+            .split_once(' ')
+            .map_or((software.1, None), |(software, comments)| {
+                (software, Some(comments.to_string()))
+            });
+        let (product, version) = software
+            .split_once('_')
+            .map_or((software, None), |(product, version)| {
+                (product, Some(version.to_string()))
+            });
+        return new_service("ssh", Some(product.to_string()), version, comments);
+    }
+
+    if line.starts_with("+PONG") || line.starts_with("-NOAUTH") {
+        return new_service("redis", Some("Redis".to_string()), None, None);
+    }
+
+    if line.starts_with("220") {
+        let upper = line.to_uppercase();
+        if upper.contains("SMTP") || upper.contains("MAIL") {
+            let (product, version) = known_product(line, SMTP_PRODUCTS);
+            return new_service("smtp", product, version, Some(line.to_string()));
+        }
+        if upper.contains("FTP") {
+            let (product, version) = known_product(line, FTP_PRODUCTS);
+            return new_service("ftp", product, version, Some(line.to_string()));
+        }
+    }
+
+    new_service("unknown", None, None, Some(printable(line)))
+}
+
+fn identify_http(response: &str) -> Service {
+    let server = response
+        .lines()
+        .take_while(|line| !line.trim().is_empty())
+        .find_map(|line| {
+            line.split_once(':')
+                .filter(|(name, _)| name.trim().eq_ignore_ascii_case("server"))
+                .map(|(_, value)| value.trim().to_string())
+        });
+    match server {
+        // nginx/1.18.0 (Ubuntu)
+        Some(server) => {
+            let (software, comments) = server
+                .split_once(' ')
+                .map_or((server.as_str(), None), |(software, comments)| {
+                    (software, Some(comments.to_string()))
+                });
+            let (product, version) = software
+                .split_once('/')
+                .map_or((software, None), |(product, version)| {
+                    (product, Some(version.to_string()))
+                });
+            new_service("http", Some(product.to_string()), version, comments)
+        }
+        None => new_service("http", None, None, None),
+    }
+}
+
+/// Find one of the known products in the banner and the version that follows it
+fn known_product(line: &str, products: &[&str]) -> (Option<String>, Option<String>) {
+    products
+        .iter()
+        .find_map(|product| {
+            line.find(product).map(|position| {
+                let version = line[position + product.len()..]
+                    .split(|c: char| c.is_whitespace() || c == ')' || c == '(' || c == ';')
+                    .find(|word| word.starts_with(|c: char| c.is_ascii_digit()))
+                    .map(str::to_string);
+                (Some((*product).to_string()), version)
+            })
+        })
+        .unwrap_or_default()
+}
+
+fn printable(text: &str) -> String {
+    text.chars().filter(|c| !c.is_control()).take(100).collect()
+}
+
+/// Minimal TLS 1.2 ClientHello, enough for any TLS server to answer with a
+/// ServerHello or an alert
+fn client_hello() -> Vec<u8> {
+    const CIPHER_SUITES: &[u16] = &[
+        0x1301, 0x1302, 0x1303, 0xc02b, 0xc02f, 0xc02c, 0xc030, 0xcca9, 0xcca8, 0xc013, 0xc014,
+        0x009c, 0x009d, 0x002f, 0x0035,
+    ];
+    let mut extensions = Vec::new();
+    // supported_groups: x25519, secp256r1, secp384r1
+    extensions.extend_from_slice(&[
+        0x00, 0x0a, 0x00, 0x08, 0x00, 0x06, 0x00, 0x1d, 0x00, 0x17, 0x00, 0x18,
+    ]);
+    // ec_point_formats: uncompressed
+    extensions.extend_from_slice(&[0x00, 0x0b, 0x00, 0x02, 0x01, 0x00]);
+    // signature_algorithms
+    extensions.extend_from_slice(&[
+        0x00, 0x0d, 0x00, 0x0c, 0x00, 0x0a, 0x04, 0x03, 0x08, 0x04, 0x04, 0x01, 0x05, 0x01, 0x02,
+        0x01,
+    ]);
+
+    let mut hello = vec![0x03, 0x03];
+    hello.extend(std::iter::repeat_with(rand::random::<u8>).take(32));
+    hello.push(0x00);
+    hello.extend_from_slice(&u16_bytes(CIPHER_SUITES.len() * 2));
+    for suite in CIPHER_SUITES {
+        hello.extend_from_slice(&suite.to_be_bytes());
+    }
+    hello.extend_from_slice(&[0x01, 0x00]);
+    hello.extend_from_slice(&u16_bytes(extensions.len()));
+    hello.extend(extensions);
+
+    let mut handshake = vec![0x01, 0x00];
+    handshake.extend_from_slice(&u16_bytes(hello.len()));
+    handshake.extend(hello);
+
+    let mut record = vec![0x16, 0x03, 0x01];
+    record.extend_from_slice(&u16_bytes(handshake.len()));
+    record.extend(handshake);
+    record
+}
+
+fn u16_bytes(value: usize) -> [u8; 2] {
+    u16::try_from(value).unwrap_or(u16::MAX).to_be_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table_port(name: &str) -> Port {
+        Port {
+            protocol: "tcp".to_string(),
+            portid: "8080".to_string(),
+            service: Some(Service {
+                name: name.to_string(),
+                method: "table".to_string(),
+                conf: "3".to_string(),
+                ..Service::default()
+            }),
+            ..Port::default()
+        }
+    }
+
+    #[test]
+    fn ssh_banner() {
+        let service = identify_banner(b"SSH-2.0-OpenSSH_9.6p1 Ubuntu-3ubuntu13\r\n");
+        assert_eq!(service.name, "ssh");
+        assert_eq!(service.product.as_deref(), Some("OpenSSH"));
+        assert_eq!(service.version.as_deref(), Some("9.6p1"));
+        assert_eq!(service.extrainfo.as_deref(), Some("Ubuntu-3ubuntu13"));
+        assert_eq!(service.method, FINGERPRINT_METHOD);
+
+        let service = identify_banner(b"SSH-2.0-dropbear\r\n");
+        assert_eq!(service.product.as_deref(), Some("dropbear"));
+        assert_eq!(service.version, None);
+    }
+
+    #[test]
+    fn smtp_and_ftp_greetings() {
+        let service = identify_banner(b"220 mail.example.com ESMTP Postfix (Ubuntu)\r\n");
+        assert_eq!(service.name, "smtp");
+        assert_eq!(service.product.as_deref(), Some("Postfix"));
+        assert_eq!(service.version, None);
+        assert_eq!(
+            service.extrainfo.as_deref(),
+            Some("220 mail.example.com ESMTP Postfix (Ubuntu)")
+        );
+
+        let service = identify_banner(b"220 (vsFTPd 3.0.5)\r\n");
+        assert_eq!(service.name, "ftp");
+        assert_eq!(service.product.as_deref(), Some("vsFTPd"));
+        assert_eq!(service.version.as_deref(), Some("3.0.5"));
+
+        let service = identify_banner(b"220 ftp.example.com FTP server ready\r\n");
+        assert_eq!(service.name, "ftp");
+        assert_eq!(service.product, None);
+    }
+
+    #[test]
+    fn unknown_banner() {
+        let service = identify_banner(b"\x01hello\x07 there\r\nsecond line");
+        assert_eq!(service.name, "unknown");
+        assert_eq!(service.product, None);
+        assert_eq!(service.extrainfo.as_deref(), Some("hello there"));
+    }
+
+    #[test]
+    fn http_server_header() {
+        let service = identify_http(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nserver: nginx/1.18.0 (Ubuntu)\r\n\r\nServer: body",
+        );
+        assert_eq!(service.name, "http");
+        assert_eq!(service.product.as_deref(), Some("nginx"));
+        assert_eq!(service.version.as_deref(), Some("1.18.0"));
+        assert_eq!(service.extrainfo.as_deref(), Some("(Ubuntu)"));
+
+        let service = identify_http("HTTP/1.1 404 Not Found\r\n\r\nServer: body");
+        assert_eq!(service.name, "http");
+        assert_eq!(service.product, None);
+    }
+
+    #[test]
+    fn known_products_and_versions() {
+        assert_eq!(
+            known_product("220 host ESMTP Exim 4.96 Mon, 1 Jan 2024", SMTP_PRODUCTS),
+            (Some("Exim".to_string()), Some("4.96".to_string()))
+        );
+        assert_eq!(
+            known_product(
+                "220 ProFTPD Server (Debian) [::ffff:192.0.2.1]",
+                FTP_PRODUCTS
+            ),
+            (Some("ProFTPD".to_string()), None)
+        );
+        assert_eq!(known_product("220 ready", FTP_PRODUCTS), (None, None));
+    }
+
+    #[test]
+    fn unknown_services_keep_the_nmap_guess() {
+        let mut port = table_port("http-proxy");
+        merge_service(&mut port, identify_banner(b"\x00\x01binary"));
+        let service = port.service.unwrap();
+        assert_eq!(service.name, "http-proxy");
+        assert_eq!(service.method, "table");
+        assert_eq!(service.conf, "3");
+        assert_eq!(service.extrainfo.as_deref(), Some("binary"));
+
+        let mut port = table_port("http-proxy");
+        merge_service(
+            &mut port,
+            identify_http("HTTP/1.0 200 OK\r\nServer: Apache\r\n\r\n"),
+        );
+        let service = port.service.unwrap();
+        assert_eq!(service.name, "http");
+        assert_eq!(service.method, FINGERPRINT_METHOD);
+        assert_eq!(service.product.as_deref(), Some("Apache"));
+    }
+}
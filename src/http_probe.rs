@@ -0,0 +1,336 @@
+use {
+    crate::{misc, nmap::Port},
+    openssl::ssl::{SslConnector, SslMethod, SslVerifyMode},
+    std::{
+        io::{Read, Write},
+        net::{IpAddr, SocketAddr, TcpStream},
+        time::Duration,
+    },
+};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+/// Bodies are only read to find the title and the length, don't download huge files
+const MAX_RESPONSE_LEN: usize = 1024 * 1024;
+const MAX_TITLE_LEN: usize = 200;
+
+/// Ports considered web ports even when Nmap did not identify the service
+const WEB_PORTS: &[u16] = &[
+    80, 81, 443, 591, 2082, 2083, 2086, 2087, 3000, 4443, 5000, 7001, 8000, 8008, 8080, 8081, 8088,
+    8443, 8800, 8888, 9000, 9090, 9443,
+];
+const TLS_PORTS: &[u16] = &[443, 2083, 2087, 4443, 8443, 9443];
+
+/// Response of a web port when requested with a given hostname
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct HttpProbe {
+    pub url: String,
+    pub status: Option<u16>,
+    pub title: Option<String>,
+    pub server: Option<String>,
+    pub location: Option<String>,
+    pub content_length: Option<usize>,
+    pub error: Option<String>,
+}
+
+/// Request every open web port of the IP using the hostname in the Host
+/// header and SNI, so every virtual host gets its own answer
+#[must_use]
+pub fn probe_host(host: &str, ip: &str, ports: &[Port]) -> Vec<HttpProbe> {
+    let ip: IpAddr = match ip.parse() {
+        Ok(ip) => ip,
+        Err(_) => return Vec::new(),
+    };
+    ports
+        .iter()
+        .filter_map(|port| {
+            let portid = port.portid.parse::<u16>().ok()?;
+            web_scheme(port, portid).map(|(tls, web)| (portid, tls, web))
+        })
+        .filter(|_| !misc::interrupted())
+        .filter_map(|(port, tls, web)| {
+            let address = SocketAddr::new(ip, port);
+            let probe = request(host, address, tls)
+                // Plain HTTP on a port that looked like TLS or the opposite
+                .or_else(|e| request(host, address, !tls).map_err(|_| e));
+            match probe {
+                Ok(probe) => Some(probe),
+                Err(e) => web.then(|| failed(host, port, tls, e)),
+            }
+        })
+        .collect()
+}
+
+/// Whether the port must be probed and if it's wrapped in TLS. The second
+/// value is false for TLS services that may not be HTTP at all, their
+/// failures are not reported
+fn web_scheme(port: &Port, portid: u16) -> Option<(bool, bool)> {
+    match &port.service {
+        Some(service) if !service.name.is_empty() => {
+            let tls = service.tunnel.as_deref() == Some("ssl")
+                || service.name.contains("https")
+                || service.name == "ssl"
+                || TLS_PORTS.contains(&portid);
+            if service.name.starts_with("http") || service.name.contains("https") {
+                Some((tls, true))
+            } else if service.name == "ssl" {
+                Some((true, WEB_PORTS.contains(&portid)))
+            } else {
+                None
+            }
+        }
+        _ => WEB_PORTS
+            .contains(&portid)
+            .then(|| (TLS_PORTS.contains(&portid), true)),
+    }
+}
+
+fn url(host: &str, port: u16, tls: bool) -> String {
+    match (tls, port) {
+        (true, 443) => format!("https://{host}/"),
+        (false, 80) => format!("http://{host}/"),
+        (true, _) => format!("https://{host}:{port}/"),
+        (false, _) => format!("http://{host}:{port}/"),
+    }
+}
+
+fn failed(host: &str, port: u16, tls: bool, error: String) -> HttpProbe {
+    HttpProbe {
+        url: url(host, port, tls),
+        error: Some(error),
+        ..HttpProbe::default()
+    }
+}
+
+fn request(host: &str, address: SocketAddr, tls: bool) -> Result<HttpProbe, String> {
+    let stream =
+        TcpStream::connect_timeout(&address, CONNECT_TIMEOUT).map_err(|e| e.to_string())?;
+    stream
+        .set_read_timeout(Some(IO_TIMEOUT))
+        .and_then(|()| stream.set_write_timeout(Some(IO_TIMEOUT)))
+        .map_err(|e| e.to_string())?;
+
+    let request = format!(
+        "GET / HTTP/1.1\r\nHost: {}\r\nUser-Agent: unimap/{}\r\nAccept: */*\r\nConnection: close\r\n\r\n",
+        host,
+        env!("CARGO_PKG_VERSION")
+    );
+    let response = if tls {
+        let mut connector = SslConnector::builder(SslMethod::tls()).map_err(|e| e.to_string())?;
+        // We want to see what is served, not to validate it
+        connector.set_verify(SslVerifyMode::NONE);
+        let mut stream = connector
+            .build()
+            .connect(host, stream)
+            .map_err(|e| format!("TLS handshake failed: {e}"))?;
+        exchange(&mut stream, request.as_bytes())?
+    } else {
+        let mut stream = stream;
+        exchange(&mut stream, request.as_bytes())?
+    };
+
+    parse_response(&response)
+        .map(|mut probe| {
+            probe.url = url(host, address.port(), tls);
+            probe
+        })
+        .ok_or_else(|| String::from("not an HTTP response"))
+}
+
+fn exchange<S: Read + Write>(stream: &mut S, request: &[u8]) -> Result<Vec<u8>, String> {
+    stream.write_all(request).map_err(|e| e.to_string())?;
+    let mut response = Vec::new();
+    let mut buffer = [0; 8192];
+    while response.len() < MAX_RESPONSE_LEN {
+        match stream.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => response.extend_from_slice(&buffer[..read]),
+            // Servers that keep the connection open or close TLS abruptly
+            Err(_) if !response.is_empty() => break,
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    Ok(response)
+}
+
+fn parse_response(response: &[u8]) -> Option<HttpProbe> {
+    let header_end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")?;
+    let head = String::from_utf8_lossy(&response[..header_end]);
+    let raw_body = &response[header_end + 4..];
+
+    let mut lines = head.lines();
+    let status = lines
+        .next()
+        .filter(|line| line.starts_with("HTTP/"))?
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok());
+
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+        .collect();
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.clone())
+    };
+
+    let body = if header("transfer-encoding").is_some_and(|value| value.contains("chunked")) {
+        dechunk(raw_body)
+    } else {
+        raw_body.to_vec()
+    };
+
+    Some(HttpProbe {
+        status,
+        title: html_title(&String::from_utf8_lossy(&body)),
+        server: header("server"),
+        location: header("location"),
+        content_length: header("content-length")
+            .and_then(|length| length.parse().ok())
+            .or(Some(body.len())),
+        ..HttpProbe::default()
+    })
+}
+
+fn dechunk(mut body: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    while let Some(line_end) = body.windows(2).position(|window| window == b"\r\n") {
+        let size = String::from_utf8_lossy(&body[..line_end]);
+        let size = usize::from_str_radix(size.split(';').next().unwrap_or_default().trim(), 16)
+            .unwrap_or_default();
+        body = &body[line_end + 2..];
+        if size == 0 {
+            break;
+        }
+        let size = size.min(body.len());
+        decoded.extend_from_slice(&body[..size]);
+        body = &body[size..];
+        body = body.strip_prefix(b"\r\n").unwrap_or(body);
+    }
+    decoded
+}
+
+fn html_title(body: &str) -> Option<String> {
+    let lowercase = body.to_ascii_lowercase();
+    let start = lowercase.find("<title")?;
+    let start = start + lowercase[start..].find('>')? + 1;
+    let end = start + lowercase[start..].find("</title")?;
+    let title = body
+        .get(start..end)?
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ");
+    if title.is_empty() {
+        None
+    } else {
+        Some(title.chars().take(MAX_TITLE_LEN).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::nmap::Service,
+        std::{net::TcpListener, thread},
+    };
+
+    /// Answer a single request with `response` and return the request
+    fn serve(response: &'static str) -> (u16, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let read = stream.read(&mut buffer).unwrap();
+                if read == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buffer[..read]);
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        (port, server)
+    }
+
+    fn http_port(port: u16) -> Port {
+        Port {
+            protocol: String::from("tcp"),
+            portid: port.to_string(),
+            service: Some(Service {
+                name: String::from("http"),
+                ..Service::default()
+            }),
+            ..Port::default()
+        }
+    }
+
+    #[test]
+    fn probe_reads_the_response() {
+        let (port, server) = serve(
+            "HTTP/1.1 200 OK\r\nServer: nginx/1.25.3\r\nContent-Type: text/html\r\nContent-Length: 49\r\n\r\n<html><head><title> Welcome\n page </title></head>",
+        );
+
+        let probes = probe_host("www.example.test", "127.0.0.1", &[http_port(port)]);
+        let request = server.join().unwrap();
+
+        assert!(request.starts_with("GET / HTTP/1.1\r\n"));
+        assert!(request.contains("\r\nHost: www.example.test\r\n"));
+        assert_eq!(
+            probes,
+            vec![HttpProbe {
+                url: format!("http://www.example.test:{port}/"),
+                status: Some(200),
+                title: Some(String::from("Welcome page")),
+                server: Some(String::from("nginx/1.25.3")),
+                location: None,
+                content_length: Some(49),
+                error: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn probe_reports_redirects() {
+        let (port, server) = serve(
+            "HTTP/1.1 301 Moved Permanently\r\nserver: Apache\r\nlocation: https://www.example.test/\r\ncontent-length: 0\r\n\r\n",
+        );
+
+        let probes = probe_host("example.test", "127.0.0.1", &[http_port(port)]);
+        let request = server.join().unwrap();
+
+        assert!(request.contains("\r\nHost: example.test\r\n"));
+        assert_eq!(
+            probes,
+            vec![HttpProbe {
+                url: format!("http://example.test:{port}/"),
+                status: Some(301),
+                title: None,
+                server: Some(String::from("Apache")),
+                location: Some(String::from("https://www.example.test/")),
+                content_length: Some(0),
+                error: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn chunked_bodies_are_decoded() {
+        let probe = parse_response(
+            b"HTTP/1.1 404 Not Found\r\nTransfer-Encoding: chunked\r\n\r\n7\r\n<title>\r\n9\r\nNot found\r\n8\r\n</title>\r\n0\r\n\r\n",
+        )
+        .unwrap();
+
+        assert_eq!(probe.status, Some(404));
+        assert_eq!(probe.title.as_deref(), Some("Not found"));
+        assert_eq!(probe.content_length, Some(24));
+    }
+}
@@ -8,14 +8,26 @@ extern crate prettytable;
 extern crate log;
 
 pub mod args;
+pub mod diff;
 pub mod errors;
 pub mod files;
 pub mod logger;
 pub mod misc;
+pub mod preflight;
+pub mod resolutions;
 pub mod resolver_engine;
 
+mod asn;
+mod cdn;
+mod cloud;
 mod defaults;
+mod fingerprint;
+mod http_probe;
 mod logic;
 mod networking;
 mod nmap;
+mod output;
+mod scanner;
+mod state;
 mod structs;
+mod tls;
@@ -1,3 +1,13 @@
+use {
+    crate::{
+        asn::AsnInfo,
+        cloud::CloudInfo,
+        fingerprint::FINGERPRINT_METHOD,
+        nmap::{OsMatch, Service},
+    },
+    std::net::IpAddr,
+};
+
 lazy_static! {
     static ref SPECIAL_CHARS: Vec<char> = vec![
         '[', ']', '{', '}', '(', ')', '*', '|', ':', '<', '>', '/', '\\', '%', '&', '¿', '?', '¡',
@@ -28,3 +38,187 @@ pub fn return_ports_string(ports: &Vec<String>) -> String {
         ports.join(";")
     }
 }
+
+pub fn return_os_string(os: &Option<OsMatch>) -> String {
+    os.as_ref().map_or_else(
+        || String::from("NULL"),
+        |os| format!("{} ({}%)", os.name, os.accuracy),
+    )
+}
+
+pub fn return_ptr_string(ptr: &[String], separator: &str) -> String {
+    if ptr.is_empty() {
+        String::from("NULL")
+    } else {
+        ptr.join(separator)
+    }
+}
+
+pub fn return_asn_string(asn: &Option<AsnInfo>) -> String {
+    asn.as_ref().map_or_else(
+        || String::from("NULL"),
+        |asn| match &asn.country {
+            Some(country) => format!("{} {} ({country})", asn.name(), asn.org),
+            None => format!("{} {}", asn.name(), asn.org),
+        },
+    )
+}
+
+pub fn return_cloud_string(cloud: &Option<CloudInfo>) -> String {
+    cloud.as_ref().map_or_else(
+        || String::from("NULL"),
+        |cloud| {
+            [
+                Some(cloud.provider.as_str()),
+                cloud.service.as_deref(),
+                cloud.region.as_deref(),
+            ]
+            .iter()
+            .flatten()
+            .copied()
+            .collect::<Vec<&str>>()
+            .join(" ")
+        },
+    )
+}
+
+/// Service name, marked when it was identified by unimap instead of Nmap
+pub fn return_service_name(service: &Option<Service>) -> String {
+    service.as_ref().map_or_else(String::new, |service| {
+        if service.method == FINGERPRINT_METHOD {
+            format!("{} ({FINGERPRINT_METHOD})", service.name)
+        } else {
+            service.name.clone()
+        }
+    })
+}
+
+/// Parse a ports specification like "22, 80, 443, 1000-5000" into a sorted list
+/// of TCP ports. The Nmap syntax is accepted too: ranges without a start or an
+/// end ("-1024", "60000-", "-" for all the ports) and the "T:" prefix. UDP
+/// ("U:") and SCTP ("S:") ports are rejected, only TCP ports can be connected to
+pub fn parse_ports(ports: &str) -> Result<Vec<u16>, String> {
+    let mut parsed = Vec::new();
+    // Like in Nmap, a protocol prefix applies until the next one
+    let mut tcp = true;
+    for part in ports
+        .split(',')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+    {
+        let part = match part.split_once(':') {
+            Some((protocol, part)) => {
+                tcp = match protocol.trim() {
+                    "T" => true,
+                    "U" | "S" => false,
+                    _ => {
+                        return Err(format!(
+                            "invalid protocol {protocol}: in {ports}, use T:, U: or S:"
+                        ))
+                    }
+                };
+                part.trim()
+            }
+            None => part,
+        };
+        if !tcp {
+            return Err(format!(
+                "UDP and SCTP ports (U: and S:) can't be scanned in {ports}, only TCP ports"
+            ));
+        }
+        if part.is_empty() {
+            continue;
+        }
+        let parse = |port: &str, missing: u16| {
+            let port = port.trim();
+            if port.is_empty() {
+                return Ok(missing);
+            }
+            match port.parse::<u16>() {
+                Ok(0) => Err(format!("port 0 can't be scanned in {ports}")),
+                Ok(port) => Ok(port),
+                Err(_) => Err(format!("invalid port {port} in {ports}")),
+            }
+        };
+        if let Some((start, end)) = part.split_once('-') {
+            let (start, end) = (parse(start, 1)?, parse(end, u16::MAX)?);
+            if start > end {
+                return Err(format!("invalid port range {part}"));
+            }
+            parsed.extend(start..=end);
+        } else {
+            parsed.push(parse(part, 0)?);
+        }
+    }
+    parsed.sort_unstable();
+    parsed.dedup();
+    if parsed.is_empty() {
+        Err(format!("no ports found in {ports}"))
+    } else {
+        Ok(parsed)
+    }
+}
+
+/// Network address and prefix length of a CIDR like "10.0.0.0/8" or "2001:db8::/32"
+pub fn parse_cidr(cidr: &str) -> Option<(IpAddr, u8)> {
+    let (network, prefix) = cidr.trim().split_once('/')?;
+    let network: IpAddr = network.parse().ok()?;
+    let prefix: u8 = prefix.parse().ok()?;
+    let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+    (prefix <= max_prefix).then_some((network, prefix))
+}
+
+pub fn cidr_contains(network: IpAddr, prefix: u8, ip: IpAddr) -> bool {
+    network.is_ipv4() == ip.is_ipv4()
+        && network_address(network, prefix) == network_address(ip, prefix)
+}
+
+/// First address of the network of `ip` with the given prefix length
+pub fn network_address(ip: IpAddr, prefix: u8) -> IpAddr {
+    match ip {
+        IpAddr::V4(ip) => {
+            let mask = u32::MAX
+                .checked_shl(32_u32.saturating_sub(prefix.into()))
+                .unwrap_or(0);
+            IpAddr::V4((u32::from(ip) & mask).into())
+        }
+        IpAddr::V6(ip) => {
+            let mask = u128::MAX
+                .checked_shl(128_u32.saturating_sub(prefix.into()))
+                .unwrap_or(0);
+            IpAddr::V6((u128::from(ip) & mask).into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ports_lists_and_ranges() {
+        assert_eq!(
+            parse_ports("443, 22,80, 1000-1002,80"),
+            Ok(vec![22, 80, 443, 1000, 1001, 1002])
+        );
+        assert!(parse_ports("80-22").is_err());
+        assert!(parse_ports("http").is_err());
+        assert!(parse_ports(" , ").is_err());
+        assert!(parse_ports("0").is_err());
+        assert!(parse_ports("22,0,80").is_err());
+        assert!(parse_ports("0-1024").is_err());
+        assert!(parse_ports("T:0-").is_err());
+    }
+
+    #[test]
+    fn parse_ports_nmap_syntax() {
+        assert_eq!(parse_ports("-").map(|ports| ports.len()), Ok(65535));
+        assert_eq!(parse_ports("65533-"), Ok(vec![65533, 65534, 65535]));
+        assert_eq!(parse_ports("-3"), Ok(vec![1, 2, 3]));
+        assert_eq!(parse_ports("T:22,80,T:443"), Ok(vec![22, 80, 443]));
+        assert!(parse_ports("U:53").is_err());
+        assert!(parse_ports("T:22,U:53,161").is_err());
+        assert!(parse_ports("S:2905").is_err());
+        assert!(parse_ports("X:22").is_err());
+    }
+}
@@ -2,7 +2,10 @@ use {
     clap::Parser,
     log::{error, Level},
     std::{collections::HashSet, iter::FromIterator},
-    unimap::{args, errors::Result, files::return_file_targets, logger, misc, resolver_engine},
+    unimap::{
+        args, diff, errors::Result, files::return_file_targets, logger, misc, resolutions,
+        resolver_engine,
+    },
 };
 
 fn run() -> Result<()> {
@@ -12,13 +15,22 @@ fn run() -> Result<()> {
         logger::init_with_level(Level::Info).unwrap();
     }
 
-    let mut arguments = args::Args::parse().into_processed_args();
+    let arguments = args::Args::parse();
+    if let Some(args::Command::Diff(diff_args)) = &arguments.command {
+        return diff::run(diff_args);
+    }
+    let mut arguments = arguments.into_processed_args();
+    if let Some(path) = &arguments.resolutions {
+        arguments.imported_resolutions = resolutions::read_resolutions(path)?;
+    }
 
     if !arguments.files.is_empty() {
         arguments.targets =
             HashSet::from_iter(return_file_targets(&arguments, arguments.files.clone()));
     } else if !arguments.target.is_empty() {
         arguments.targets.insert(arguments.target.clone());
+    } else if arguments.resolutions.is_some() && !arguments.from_stdin {
+        arguments.targets = arguments.imported_resolutions.keys().cloned().collect();
     } else {
         arguments.targets = misc::read_stdin();
     }
@@ -1,8 +1,16 @@
-use std::{
-    collections::HashSet,
-    io::{self, Read},
+use {
+    crate::networking,
+    log::warn,
+    std::{
+        collections::HashSet,
+        io::{self, Read},
+        sync::atomic::{AtomicBool, Ordering},
+        time::Duration,
+    },
 };
 
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
 #[must_use]
 pub fn sanitize_target_string(target: String) -> String {
     target
@@ -20,3 +28,102 @@ pub fn read_stdin() -> HashSet<String> {
         .expect("Error getting input list.");
     buffer.lines().map(str::to_owned).collect()
 }
+
+/// Parse a duration using the Nmap syntax: a number optionally followed by
+/// `ms`, `s`, `m` or `h`, and `d` for days. Plain numbers are seconds
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration {value}"))?;
+    if number == 0 {
+        return Err(format!(
+            "invalid duration {value}, it must be greater than 0"
+        ));
+    }
+    let seconds = |factor: u64| {
+        number
+            .checked_mul(factor)
+            .map(Duration::from_secs)
+            .ok_or_else(|| "duration too large".to_string())
+    };
+    match unit {
+        "ms" => Ok(Duration::from_millis(number)),
+        "" | "s" => Ok(Duration::from_secs(number)),
+        "m" => seconds(60),
+        "h" => seconds(3600),
+        "d" => seconds(86400),
+        _ => Err(format!(
+            "invalid duration unit {unit}, use ms, s, m, h or d"
+        )),
+    }
+}
+
+/// Parse a CSV delimiter, a single ASCII character or `tab`
+pub fn parse_delimiter(value: &str) -> Result<u8, String> {
+    match value {
+        "tab" | "\\t" => Ok(b'\t'),
+        _ if value.len() == 1 && value.is_ascii() => Ok(value.as_bytes()[0]),
+        _ => Err(format!(
+            "invalid delimiter {value}, use a single ASCII character or tab"
+        )),
+    }
+}
+
+/// Catch Ctrl-C so running scans can be stopped and the results collected so
+/// far still get written. A second Ctrl-C exits immediately
+pub fn handle_interrupts() {
+    networking::spawn(async {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            if INTERRUPTED.swap(true, Ordering::SeqCst) {
+                std::process::exit(130)
+            }
+            warn!("Interrupted, stopping the running scans and writing partial results. Press Ctrl-C again to exit immediately.\n");
+        }
+    });
+}
+
+#[must_use]
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_duration_units() {
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("30"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+        assert_eq!(parse_duration(" 7d "), Ok(Duration::from_secs(604_800)));
+        assert!(parse_duration("5w").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("-5s").is_err());
+    }
+
+    #[test]
+    fn parse_duration_rejects_zero_and_overflow() {
+        assert!(parse_duration("0").is_err());
+        assert!(parse_duration("0ms").is_err());
+        assert!(parse_duration("0d").is_err());
+
+        let too_large = format!("{}d", u64::MAX / 86400 + 1);
+        assert_eq!(
+            parse_duration(&too_large),
+            Err("duration too large".to_string())
+        );
+        assert_eq!(
+            parse_duration(&format!("{}d", u64::MAX / 86400)),
+            Ok(Duration::from_secs(u64::MAX / 86400 * 86400))
+        );
+        assert!(parse_duration(&format!("{}h", u64::MAX)).is_err());
+    }
+}
@@ -6,22 +6,68 @@ use {
         proto::xfer::Protocol,
         TokioResolver,
     },
-    std::{collections::HashSet, net::SocketAddr},
+    std::{
+        collections::HashSet,
+        net::{IpAddr, SocketAddr},
+    },
+    tokio::runtime::{Builder, Runtime},
 };
 
-pub fn get_records(resolver: &TokioResolver, domain: &str) -> String {
-    futures::executor::block_on(resolver.ipv4_lookup(domain)).map_or_else(
-        |_| String::new(),
-        |ips| {
-            ips.iter()
+lazy_static! {
+    /// Runtime driving the resolver connections, shared by all the rayon workers
+    static ref RUNTIME: Runtime = Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to create the Tokio runtime.");
+}
+
+/// All the IPv4 addresses of the domain, in the order returned by the resolver,
+/// and the targets of the CNAME records followed to get them, without the trailing dot
+pub fn get_records(resolver: &TokioResolver, domain: &str) -> (Vec<String>, Vec<String>) {
+    RUNTIME
+        .block_on(resolver.ipv4_lookup(domain))
+        .map(|lookup| {
+            let ips = lookup
+                .iter()
                 .map(std::string::ToString::to_string)
-                .next()
-                .expect("Failed to get IPV4.")
-        },
-    )
+                .collect();
+            let cnames = lookup
+                .as_lookup()
+                .record_iter()
+                .filter_map(|record| record.data().as_cname())
+                .map(|cname| cname.to_string().trim_end_matches('.').to_string())
+                .collect();
+            (ips, cnames)
+        })
+        .unwrap_or_default()
+}
+
+/// Names of the PTR records of the IP, without the trailing dot
+pub fn get_ptr_records(resolver: &TokioResolver, ip: IpAddr) -> Vec<String> {
+    RUNTIME
+        .block_on(resolver.reverse_lookup(ip))
+        .map(|names| {
+            names
+                .iter()
+                .map(|name| name.to_utf8().trim_end_matches('.').to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    RUNTIME.block_on(future)
+}
+
+pub fn spawn<F>(future: F)
+where
+    F: std::future::Future<Output = ()> + Send + 'static,
+{
+    RUNTIME.spawn(future);
 }
 
 pub fn get_resolver(nameserver_ips: HashSet<SocketAddr>, opts: ResolverOpts) -> TokioResolver {
+    let _guard = RUNTIME.enter();
     let mut name_servers = NameServerConfigGroup::with_capacity(nameserver_ips.len());
     name_servers.extend(
         nameserver_ips
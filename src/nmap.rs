@@ -1,77 +1,122 @@
 use {
-    log::error,
-    std::{net::Ipv4Addr, path::Path, process::Command},
+    crate::{args::ProcessedArgs, scanner, structs::ScanStatus},
+    std::{
+        net::Ipv4Addr,
+        path::Path,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    },
 };
 
-#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct Root {
-    pub nmaprun: Nmaprun,
-}
-
 #[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Nmaprun {
+    #[serde(rename = "@scanner")]
     pub scanner: String,
+    #[serde(rename = "@args")]
     pub args: String,
+    #[serde(rename = "@start")]
     pub start: String,
+    #[serde(rename = "@startstr")]
     pub startstr: String,
+    #[serde(rename = "@version")]
     pub version: String,
+    #[serde(rename = "@xmloutputversion")]
     pub xmloutputversion: String,
     pub host: Option<Host>,
 }
 
+/// Every host of a Nmap XML file, the files written by a scan of several IPs
+/// have one per IP with other elements between them
+#[derive(Default, Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct NmapHosts {
+    #[serde(rename = "#content", default)]
+    elements: Vec<NmapElement>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum NmapElement {
+    Host(Box<Host>),
+    #[serde(other)]
+    Other,
+}
+
+impl NmapHosts {
+    pub fn hosts(self) -> impl Iterator<Item = Host> {
+        self.elements
+            .into_iter()
+            .filter_map(|element| match element {
+                NmapElement::Host(host) => Some(*host),
+                NmapElement::Other => None,
+            })
+    }
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Host {
+    #[serde(rename = "@starttime")]
     pub starttime: String,
+    #[serde(rename = "@endtime")]
     pub endtime: String,
+    #[serde(rename = "@timedout")]
+    pub timedout: Option<String>,
     pub status: Status,
     pub address: Address,
     pub hostnames: Hostnames,
     pub ports: Option<Ports>,
+    pub os: Option<Os>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Status {
+    #[serde(rename = "@state")]
     pub state: String,
+    #[serde(rename = "@reason")]
     pub reason: String,
-    #[serde(rename = "reason_ttl")]
+    #[serde(rename = "@reason_ttl")]
     pub reason_ttl: String,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Address {
+    #[serde(rename = "@addr")]
     pub addr: Option<String>,
+    #[serde(rename = "@addrtype")]
     pub addrtype: Option<String>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Hostnames {
-    pub hostname: Option<Hostname>,
+    #[serde(default)]
+    pub hostname: Vec<Hostname>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Hostname {
+    #[serde(rename = "@name")]
     pub name: String,
-    #[serde(rename = "type")]
+    #[serde(rename = "@type")]
     pub type_field: String,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Ports {
+    #[serde(default)]
     pub port: Vec<Port>,
 }
 
 #[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Hash)]
 #[serde(rename_all = "camelCase")]
 pub struct Port {
+    #[serde(rename = "@protocol")]
     pub protocol: String,
+    #[serde(rename = "@portid")]
     pub portid: String,
     pub state: State,
     pub service: Option<Service>,
@@ -80,85 +125,451 @@ pub struct Port {
 #[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Hash)]
 #[serde(rename_all = "camelCase")]
 pub struct State {
+    #[serde(rename = "@state")]
     pub state: String,
+    #[serde(rename = "@reason")]
     pub reason: String,
-    #[serde(rename = "reason_ttl")]
+    #[serde(rename = "@reason_ttl")]
     pub reason_ttl: String,
 }
 
 #[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Hash)]
 #[serde(rename_all = "camelCase")]
 pub struct Service {
+    #[serde(rename = "@name")]
     pub name: String,
+    #[serde(rename = "@method")]
     pub method: String,
+    #[serde(rename = "@conf")]
     pub conf: String,
+    #[serde(rename = "@product")]
     pub product: Option<String>,
+    #[serde(rename = "@ostype")]
     pub ostype: Option<String>,
+    #[serde(rename = "@version")]
     pub version: Option<String>,
+    #[serde(rename = "@extrainfo")]
     pub extrainfo: Option<String>,
+    #[serde(rename = "@tunnel")]
+    pub tunnel: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Os {
+    #[serde(default)]
+    pub osmatch: Vec<OsMatch>,
+}
+
+#[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Hash)]
+#[serde(rename_all = "camelCase")]
+pub struct OsMatch {
+    #[serde(rename = "@name")]
+    pub name: String,
+    #[serde(rename = "@accuracy")]
+    pub accuracy: String,
+    #[serde(default)]
+    pub osclass: Vec<OsClass>,
+}
+
+#[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Hash)]
+#[serde(rename_all = "camelCase")]
+pub struct OsClass {
+    #[serde(rename = "@type")]
+    pub type_field: Option<String>,
+    #[serde(rename = "@vendor")]
+    pub vendor: Option<String>,
+    #[serde(rename = "@osfamily")]
+    pub osfamily: Option<String>,
+    #[serde(rename = "@osgen")]
+    pub osgen: Option<String>,
+    #[serde(rename = "@accuracy")]
+    pub accuracy: String,
+}
+
+impl OsMatch {
+    #[must_use]
+    pub fn accuracy(&self) -> u8 {
+        self.accuracy.parse().unwrap_or_default()
+    }
+}
+
+impl Host {
+    /// Whether Nmap gave up on the host because of `--host-timeout`
+    #[must_use]
+    pub fn timed_out(&self) -> bool {
+        self.timedout.as_deref() == Some("true")
+    }
+
+    /// Open ports found by Nmap
+    #[must_use]
+    pub fn open_ports(&self) -> Vec<Port> {
+        self.ports
+            .as_ref()
+            .map(|ports| {
+                ports
+                    .port
+                    .iter()
+                    .filter(|port| port.state.state == "open")
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Return the OS match with the highest accuracy reported by Nmap, if any
+    #[must_use]
+    pub fn best_os_match(&self) -> Option<OsMatch> {
+        self.os
+            .as_ref()?
+            .osmatch
+            .iter()
+            // Nmap lists matches by accuracy, reverse so ties keep the first one
+            .rev()
+            .max_by_key(|osmatch| osmatch.accuracy())
+            .cloned()
+    }
+}
+
+impl Nmaprun {
+    /// Whether Nmap gave up on the host because of `--host-timeout`
+    #[must_use]
+    pub fn timed_out(&self) -> bool {
+        self.host.as_ref().is_some_and(Host::timed_out)
+    }
+
+    /// Open ports found by Nmap
+    #[must_use]
+    pub fn open_ports(&self) -> Vec<Port> {
+        self.host.as_ref().map(Host::open_ports).unwrap_or_default()
+    }
+
+    /// Return the OS match with the highest accuracy reported by Nmap, if any
+    #[must_use]
+    pub fn best_os_match(&self) -> Option<OsMatch> {
+        self.host.as_ref()?.best_os_match()
+    }
 }
 
 pub fn get_nmap_data(
+    args: &ProcessedArgs,
     filename: &str,
     host: &str,
-    min_rate: &str,
     ports: &str,
-    fast_scan: bool,
     resolvers: &[Ipv4Addr],
-) -> Result<Nmaprun, serde_xml_rs::Error> {
-    let min_rate = min_rate.to_string();
+) -> Result<Nmaprun, ScanStatus> {
     let nmap_dns_resolvers = resolvers
         .iter()
         .map(ToString::to_string)
         .collect::<Vec<String>>()
         .join(",");
 
-    let mut nmap_args = vec![
-        "nmap",
-        "--dns-servers",
-        &nmap_dns_resolvers,
+    let scan_options = scan_options(args, host, ports);
+    let mut nmap_args = vec!["--dns-servers", &nmap_dns_resolvers, "-oX", filename];
+    nmap_args.extend(scan_options.iter().map(String::as_str));
+
+    // A file left by a previous run must never be mistaken for this scan output
+    if Path::new(filename).is_file() {
+        if let Err(e) = std::fs::remove_file(filename) {
+            return Err(ScanStatus::NmapError(format!(
+                "can't remove stale output file {filename}: {e}"
+            )));
+        }
+    }
+
+    scanner::run_command(
+        &args.nmap_path,
+        &nmap_args,
+        args.host_timeout,
+        ScanStatus::NmapError,
+    )?;
+
+    match std::fs::read_to_string(filename) {
+        Ok(xml) => serde_xml_rs::from_str(&xml).map_err(|e| ScanStatus::ParseError(e.to_string())),
+        Err(e) => Err(ScanStatus::NmapError(format!(
+            "Nmap exited successfully but {filename} can't be read: {e}"
+        ))),
+    }
+}
+
+/// Reuse the XML written by a previous scan of `host` when it's complete, not
+/// older than `max_age` and was run with the same options and ports
+#[must_use]
+pub fn cached_nmap_data(
+    args: &ProcessedArgs,
+    filename: &str,
+    host: &str,
+    ports: &str,
+    max_age: Duration,
+) -> Option<Nmaprun> {
+    let xml = std::fs::read_to_string(filename).ok()?;
+    // Nmap only closes the root element when the scan finishes
+    if !xml.trim_end().ends_with("</nmaprun>") {
+        return None;
+    }
+    let nmap_data: Nmaprun = serde_xml_rs::from_str(&xml).ok()?;
+    let age = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_secs()
+        .checked_sub(nmap_data.start.parse().ok()?)?;
+    if age > max_age.as_secs() || nmap_data.timed_out() {
+        return None;
+    }
+
+    // Skip the program name and the options that don't change the results
+    let mut saved_args = nmap_data.args.split_whitespace().skip(1);
+    let mut saved_options = Vec::new();
+    while let Some(option) = saved_args.next() {
+        if option == "--dns-servers" || option == "-oX" {
+            saved_args.next();
+        } else {
+            saved_options.push(option);
+        }
+    }
+    let options = scan_options(args, host, ports).join(" ");
+    (saved_options == options.split_whitespace().collect::<Vec<&str>>()).then_some(nmap_data)
+}
+
+/// Nmap options that change the results of a scan, without the output file
+/// and the DNS servers
+fn scan_options(args: &ProcessedArgs, host: &str, ports: &str) -> Vec<String> {
+    let mut options = vec![
         "-Pn",
-        "-sS",
+        &args.scan_type,
         "--open",
         "-dd",
         "-T4",
         "--max-retries",
         "3",
-        "-oX",
-        filename,
     ];
 
-    if !min_rate.is_empty() {
-        nmap_args.append(&mut vec!["--min-rate", &min_rate]);
+    if args.nmap_privileged_flag {
+        options.push("--privileged");
+    }
+
+    if !args.min_rate.is_empty() {
+        options.append(&mut vec!["--min-rate", &args.min_rate]);
     }
 
-    if fast_scan {
-        nmap_args.append(&mut vec!["--host-timeout", "20m"]);
-    } else {
-        nmap_args.append(&mut vec!["-sV"]);
+    // Milliseconds so sub-second timeouts aren't rounded down to 0, which
+    // Nmap takes as no timeout at all
+    let host_timeout = args
+        .host_timeout
+        .map(|timeout| format!("{}ms", timeout.as_millis()));
+    if let Some(host_timeout) = &host_timeout {
+        options.append(&mut vec!["--host-timeout", host_timeout]);
+    }
+
+    if !args.fast_scan {
+        options.push("-sV");
+    }
+
+    if args.os_detection {
+        options.append(&mut vec!["-O", "--osscan-guess"]);
     }
 
     if !ports.is_empty() {
-        nmap_args.append(&mut vec!["-p", ports]);
+        options.append(&mut vec!["-p", ports]);
     }
 
-    nmap_args.push(host);
+    options.push(host);
+    options.into_iter().map(ToString::to_string).collect()
+}
 
-    match Command::new("nmap").args(&nmap_args).output() {
-        Ok(_) => {
-            if Path::new(&filename).exists() && Path::new(&filename).is_file() {
-                serde_xml_rs::from_str(&std::fs::read_to_string(filename).unwrap_or_default())
-            } else {
-                error!("Error executing nmap, possible causes: Nmap is not installed or you need root/administrator permissions. Leaving.\n");
-                println!();
-                std::process::exit(1)
-            }
-        }
-        Err(e) => {
-            error!(
-                    "Error waiting command to finish for {host}, continuing with remaining hosts. Description: {e}"
-                );
-            Ok(Nmaprun::default())
-        }
+#[cfg(test)]
+mod tests {
+    use {super::*, clap::Parser};
+
+    const SCAN: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE nmaprun>
+<nmaprun scanner="nmap" args="nmap -Pn -sS -O 192.0.2.1" start="1700000000" startstr="Tue Nov 14 22:13:20 2023" version="7.94" xmloutputversion="1.05">
+<scaninfo type="syn" protocol="tcp" numservices="1000" services="1-1000"/>
+<host starttime="1700000000" endtime="1700000042"><status state="up" reason="user-set" reason_ttl="0"/>
+<address addr="192.0.2.1" addrtype="ipv4"/>
+<hostnames>
+<hostname name="www.example.com" type="user"/>
+<hostname name="edge.example.net" type="PTR"/>
+</hostnames>
+<ports><extraports state="closed" count="998"/>
+<port protocol="tcp" portid="22"><state state="open" reason="syn-ack" reason_ttl="64"/><service name="ssh" product="OpenSSH" version="9.6p1" method="probed" conf="10"/></port>
+<port protocol="tcp" portid="80"><state state="filtered" reason="no-response" reason_ttl="0"/><service name="http" method="table" conf="3"/></port>
+</ports>
+<os><portused state="open" proto="tcp" portid="22"/>
+<osmatch name="Linux 4.15 - 5.8" accuracy="96" line="67141">
+<osclass type="general purpose" vendor="Linux" osfamily="Linux" osgen="4.X" accuracy="96"/>
+<osclass type="general purpose" vendor="Linux" osfamily="Linux" osgen="5.X" accuracy="96"/>
+</osmatch>
+<osmatch name="Linux 5.0 - 5.5" accuracy="98" line="67265">
+<osclass type="general purpose" vendor="Linux" osfamily="Linux" osgen="5.X" accuracy="98"/>
+</osmatch>
+<osmatch name="Linux 5.4" accuracy="98" line="67300"/>
+<osfingerprint fingerprint="OS:SCAN(V=7.94)"/>
+</os>
+</host>
+<runstats><finished time="1700000042" timestr="Tue Nov 14 22:14:02 2023" elapsed="42" exit="success"/><hosts up="1" down="0" total="1"/></runstats>
+</nmaprun>
+"#;
+
+    #[test]
+    fn parse_os_matches() {
+        let nmap_data: Nmaprun = serde_xml_rs::from_str(SCAN).unwrap();
+        let host = nmap_data.host.as_ref().unwrap();
+
+        assert_eq!(host.address.addr.as_deref(), Some("192.0.2.1"));
+        assert_eq!(host.hostnames.hostname.len(), 2);
+        let open_ports = nmap_data.open_ports();
+        let ports: Vec<&str> = open_ports.iter().map(|port| port.portid.as_str()).collect();
+        assert_eq!(ports, vec!["22"]);
+
+        let matches = &host.os.as_ref().unwrap().osmatch;
+        assert_eq!(matches.len(), 3);
+        assert_eq!(matches[0].name, "Linux 4.15 - 5.8");
+        assert_eq!(matches[0].accuracy(), 96);
+        assert_eq!(matches[0].osclass.len(), 2);
+        assert_eq!(matches[0].osclass[1].osgen.as_deref(), Some("5.X"));
+        assert_eq!(matches[1].osclass[0].vendor.as_deref(), Some("Linux"));
+    }
+
+    #[test]
+    fn best_os_match_is_the_most_accurate_one() {
+        let nmap_data: Nmaprun = serde_xml_rs::from_str(SCAN).unwrap();
+
+        // Ties keep the first match listed by Nmap
+        let best = nmap_data.best_os_match().unwrap();
+        assert_eq!(best.name, "Linux 5.0 - 5.5");
+        assert_eq!(best.accuracy(), 98);
+
+        let mut without_os = nmap_data;
+        without_os.host.as_mut().unwrap().os = None;
+        assert_eq!(without_os.best_os_match(), None);
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    /// Write an Nmap XML output to the temp directory, as Nmap would have
+    /// written it for `options` when started `age` seconds ago
+    fn fixture(name: &str, options: &[String], age: u64, timedout: bool) -> String {
+        let path =
+            std::env::temp_dir().join(format!("unimap-cache-{name}-{}.xml", std::process::id()));
+        let start = now() - age;
+        let timedout = if timedout { " timedout=\"true\"" } else { "" };
+        let xml = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <nmaprun scanner=\"nmap\" args=\"/usr/bin/nmap --dns-servers 1.1.1.1,8.8.8.8 -oX {} {}\" start=\"{start}\" startstr=\"Mon Jan  1 00:00:00 2024\" version=\"7.94\" xmloutputversion=\"1.05\">\n\
+             <host starttime=\"{start}\" endtime=\"{}\"{timedout}><status state=\"up\" reason=\"user-set\" reason_ttl=\"0\"/>\n\
+             <address addr=\"192.0.2.1\" addrtype=\"ipv4\"/>\n\
+             <hostnames/>\n\
+             <ports><port protocol=\"tcp\" portid=\"443\"><state state=\"open\" reason=\"syn-ack\" reason_ttl=\"64\"/><service name=\"https\" method=\"table\" conf=\"3\"/></port></ports>\n\
+             </host>\n\
+             <runstats><finished time=\"{}\" elapsed=\"30\" exit=\"success\"/><hosts up=\"1\" down=\"0\" total=\"1\"/></runstats>\n\
+             </nmaprun>\n",
+            path.display(),
+            options.join(" "),
+            start + 30,
+            start + 30
+        );
+        std::fs::write(&path, xml).unwrap();
+        path.display().to_string()
+    }
+
+    fn args(options: &[&str]) -> ProcessedArgs {
+        let mut args = vec!["unimap", "--quiet"];
+        args.extend_from_slice(options);
+        crate::args::Args::parse_from(args).into_processed_args()
+    }
+
+    fn cached(args: &ProcessedArgs, path: &str, ports: &str, max_age: u64) -> bool {
+        let cached = cached_nmap_data(args, path, "192.0.2.1", ports, Duration::from_secs(max_age));
+        cached.is_some_and(|nmap_data| nmap_data.open_ports().len() == 1)
+    }
+
+    #[test]
+    fn scan_cache_hits_with_the_same_options() {
+        let args = args(&["--host-timeout", "10m", "-O"]);
+        let path = fixture(
+            "hit",
+            &scan_options(&args, "192.0.2.1", "443,8443"),
+            60,
+            false,
+        );
+        let hit = cached(&args, &path, "443,8443", 3600);
+        std::fs::remove_file(&path).unwrap();
+        assert!(hit);
+    }
+
+    #[test]
+    fn scan_cache_misses_with_other_options() {
+        let saved = args(&["--host-timeout", "10m"]);
+        let path = fixture("miss", &scan_options(&saved, "192.0.2.1", "443"), 60, false);
+        let other_ports = cached(&saved, &path, "443,8443", 3600);
+        let fast_scan = cached(
+            &args(&["--host-timeout", "10m", "--fast-scan"]),
+            &path,
+            "443",
+            3600,
+        );
+        let no_timeout = cached(&args(&[]), &path, "443", 3600);
+        let other_host =
+            cached_nmap_data(&saved, &path, "192.0.2.2", "443", Duration::from_secs(3600));
+        let same = cached(&saved, &path, "443", 3600);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(!other_ports);
+        assert!(!fast_scan);
+        assert!(!no_timeout);
+        assert!(other_host.is_none());
+        assert!(same);
+    }
+
+    #[test]
+    fn scan_cache_age_limit() {
+        let args = args(&[]);
+        let path = fixture("age", &scan_options(&args, "192.0.2.1", "443"), 7200, false);
+        let fresh = cached(&args, &path, "443", 3 * 3600);
+        let old = cached(&args, &path, "443", 3600);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(fresh);
+        assert!(!old);
+    }
+
+    #[test]
+    fn scan_cache_skips_timed_out_hosts() {
+        let args = args(&[]);
+        let path = fixture(
+            "timedout",
+            &scan_options(&args, "192.0.2.1", "443"),
+            60,
+            true,
+        );
+        let timed_out = cached(&args, &path, "443", 3600);
+        std::fs::remove_file(&path).unwrap();
+        assert!(!timed_out);
+    }
+
+    #[test]
+    fn scan_cache_skips_incomplete_files() {
+        let args = args(&[]);
+        let path = fixture(
+            "incomplete",
+            &scan_options(&args, "192.0.2.1", "443"),
+            60,
+            false,
+        );
+        let xml = std::fs::read_to_string(&path).unwrap();
+        // Nmap was killed before closing the root element
+        std::fs::write(&path, &xml[..xml.find("<runstats>").unwrap()]).unwrap();
+        let incomplete = cached(&args, &path, "443", 3600);
+        let missing = cached(&args, &format!("{path}.missing"), "443", 3600);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(!incomplete);
+        assert!(!missing);
     }
 }
@@ -0,0 +1,328 @@
+use {
+    crate::{
+        args::{JsonlMode, OutputFormat, OutputSink},
+        asn::AsnInfo,
+        cloud::CloudInfo,
+        errors::Result,
+        http_probe::HttpProbe,
+        nmap::{Port, Service},
+        structs::ResolvData,
+        tls::TlsInfo,
+    },
+    chrono::Utc,
+    clap::ValueEnum,
+    failure::bail,
+    std::{collections::HashMap, io::Write},
+};
+
+pub mod csv;
+pub mod grepable;
+pub mod html;
+pub mod json;
+pub mod markdown;
+pub mod sqlite;
+pub mod url;
+pub mod xml;
+
+/// Version of the structured output schema, bumped on incompatible changes
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Results of a whole run, the document written by `--json`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Report {
+    pub schema_version: u32,
+    pub unimap_version: String,
+    pub generated_at: String,
+    pub hosts: Vec<HostResult>,
+}
+
+/// Everything unimap knows about a host
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostResult {
+    pub host: String,
+    /// Scanned IP, null when the host didn't resolve
+    pub ip: Option<String>,
+    /// Every IPv4 address the host resolved to
+    pub ips: Vec<String>,
+    pub ptr: Vec<String>,
+    pub asn: Option<AsnInfo>,
+    pub cdn: Option<String>,
+    pub cloud: Option<CloudInfo>,
+    /// One of not_scanned, ok, nmap_error, discovery_error, timeout, parse_error or interrupted
+    pub scan_status: String,
+    pub scan_error: Option<String>,
+    pub os: Option<OsGuess>,
+    pub ports: Vec<PortResult>,
+    pub http_probes: Vec<HttpProbe>,
+    pub tls: Vec<TlsInfo>,
+    /// Nmap XML file of the IP written or reused by this run, not part of
+    /// the JSON output
+    #[serde(skip)]
+    pub nmap_xml: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OsGuess {
+    pub name: String,
+    pub accuracy: u8,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PortResult {
+    pub port: u16,
+    pub protocol: String,
+    pub state: String,
+    pub reason: String,
+    pub service: Option<ServiceResult>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServiceResult {
+    pub name: String,
+    pub product: Option<String>,
+    pub version: Option<String>,
+    pub extrainfo: Option<String>,
+    pub ostype: Option<String>,
+    pub tunnel: Option<String>,
+    /// How the service was identified: probed or table (Nmap) or banner (unimap)
+    pub method: String,
+    pub confidence: u8,
+}
+
+/// Fail when more than one output would be printed to stdout
+pub fn check_outputs(outputs: &[OutputSink]) -> Result<()> {
+    let printed: Vec<String> = outputs
+        .iter()
+        .filter(|output| output.path.is_none())
+        .filter_map(|output| output.format.to_possible_value())
+        .map(|value| value.get_name().to_string())
+        .collect();
+    if printed.len() > 1 {
+        bail!(
+            "Only one output can be printed, got {}. Write the others to files with --out FORMAT:FILE",
+            printed.join(", ")
+        )
+    }
+    Ok(())
+}
+
+/// Write the report in one of the formats that only depend on the results.
+/// The table, CSV, HTML and Nmap XML outputs need more context and are
+/// written by their own functions
+pub fn write_report<W: Write>(report: &Report, format: OutputFormat, writer: W) -> Result<()> {
+    match format {
+        OutputFormat::Url => url::write_urls(report, writer),
+        OutputFormat::Json => json::write_json(report, writer),
+        OutputFormat::Jsonl => json::write_jsonl(report, JsonlMode::Host, writer),
+        OutputFormat::JsonlPort => json::write_jsonl(report, JsonlMode::Port, writer),
+        OutputFormat::Grepable => grepable::write_grepable(report, writer),
+        OutputFormat::Markdown => markdown::write_markdown(report, writer),
+        OutputFormat::Table
+        | OutputFormat::Csv
+        | OutputFormat::Html
+        | OutputFormat::NmapXml
+        | OutputFormat::Sqlite => {
+            bail!(
+                "The {:?} output can't be written from the report alone",
+                format
+            )
+        }
+    }
+}
+
+impl Report {
+    /// Build the report of the run, hosts are sorted by name
+    #[must_use]
+    pub fn new(data: &HashMap<String, ResolvData>) -> Self {
+        let mut hosts: Vec<HostResult> = data
+            .iter()
+            .map(|(host, resolv_data)| HostResult::new(host, resolv_data))
+            .collect();
+        hosts.sort_by(|a, b| a.host.cmp(&b.host));
+        Self {
+            schema_version: SCHEMA_VERSION,
+            unimap_version: env!("CARGO_PKG_VERSION").to_string(),
+            generated_at: Utc::now().to_rfc3339(),
+            hosts,
+        }
+    }
+}
+
+impl HostResult {
+    #[must_use]
+    pub fn new(host: &str, resolv_data: &ResolvData) -> Self {
+        Self {
+            host: host.to_string(),
+            ip: Some(resolv_data.ip.clone()).filter(|ip| !ip.is_empty()),
+            ips: resolv_data.ips.clone(),
+            ptr: resolv_data.ptr.clone(),
+            asn: resolv_data.asn.clone(),
+            cdn: resolv_data.cdn.clone(),
+            cloud: resolv_data.cloud.clone(),
+            scan_status: resolv_data.scan_status.code().to_string(),
+            scan_error: resolv_data.scan_status.detail().map(str::to_string),
+            os: resolv_data.os.as_ref().map(|os| OsGuess {
+                name: os.name.clone(),
+                accuracy: os.accuracy(),
+            }),
+            ports: resolv_data
+                .ports_data
+                .iter()
+                .map(PortResult::from)
+                .collect(),
+            http_probes: resolv_data.http_probes.clone(),
+            tls: resolv_data.tls.clone(),
+            nmap_xml: resolv_data.nmap_xml.clone(),
+        }
+    }
+}
+
+impl ServiceResult {
+    /// Product, version and extra info, e.g. `OpenSSH 8.9p1 (protocol 2.0)`
+    #[must_use]
+    pub fn description(&self) -> String {
+        let mut description: Vec<String> = [&self.product, &self.version]
+            .iter()
+            .filter_map(|value| (*value).clone())
+            .collect();
+        if let Some(extrainfo) = &self.extrainfo {
+            description.push(format!("({extrainfo})"));
+        }
+        description.join(" ")
+    }
+}
+
+impl From<&Port> for PortResult {
+    fn from(port: &Port) -> Self {
+        Self {
+            port: port.portid.parse().unwrap_or_default(),
+            protocol: port.protocol.clone(),
+            state: port.state.state.clone(),
+            reason: port.state.reason.clone(),
+            service: port.service.as_ref().map(ServiceResult::from),
+        }
+    }
+}
+
+impl From<&Service> for ServiceResult {
+    fn from(service: &Service) -> Self {
+        Self {
+            name: service.name.clone(),
+            product: service.product.clone(),
+            version: service.version.clone(),
+            extrainfo: service.extrainfo.clone(),
+            ostype: service.ostype.clone(),
+            tunnel: service.tunnel.clone(),
+            method: service.method.clone(),
+            confidence: service.conf.parse().unwrap_or_default(),
+        }
+    }
+}
+
+/// Escape text for HTML and XML, both in content and attribute values
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Report shared by the tests of the outputs: a scanned host with every
+/// enrichment and a host that didn't resolve
+#[cfg(test)]
+pub(crate) fn sample_report() -> Report {
+    use crate::tls::Certificate;
+
+    let service = |name: &str, product: &str, version: &str| ServiceResult {
+        name: name.to_string(),
+        product: Some(product.to_string()),
+        version: Some(version.to_string()),
+        method: String::from("probed"),
+        confidence: 10,
+        ..ServiceResult::default()
+    };
+    let port = |port: u16, service: Option<ServiceResult>| PortResult {
+        port,
+        protocol: String::from("tcp"),
+        state: String::from("open"),
+        reason: String::from("syn-ack"),
+        service,
+    };
+    Report {
+        schema_version: SCHEMA_VERSION,
+        unimap_version: String::from("0.7.0"),
+        generated_at: String::from("2024-01-01T00:00:00+00:00"),
+        hosts: vec![
+            HostResult {
+                host: String::from("missing.example.com"),
+                scan_status: String::from("not_scanned"),
+                ..HostResult::default()
+            },
+            HostResult {
+                host: String::from("www.example.com"),
+                ip: Some(String::from("93.184.216.34")),
+                ips: vec![String::from("93.184.216.34"), String::from("93.184.216.35")],
+                ptr: vec![String::from("edge.example.net")],
+                asn: Some(AsnInfo {
+                    number: 15133,
+                    org: String::from("EDGECAST"),
+                    country: Some(String::from("US")),
+                }),
+                cdn: None,
+                cloud: Some(CloudInfo {
+                    provider: String::from("AWS"),
+                    region: Some(String::from("us-east-1")),
+                    service: Some(String::from("EC2")),
+                }),
+                scan_status: String::from("ok"),
+                scan_error: None,
+                os: Some(OsGuess {
+                    name: String::from("Linux 5.4"),
+                    accuracy: 95,
+                }),
+                ports: vec![
+                    port(22, Some(service("ssh", "OpenSSH", "8.9p1"))),
+                    port(
+                        443,
+                        Some(ServiceResult {
+                            extrainfo: Some(String::from("Ubuntu")),
+                            tunnel: Some(String::from("ssl")),
+                            ..service("http", "nginx", "1.18.0")
+                        }),
+                    ),
+                    port(8080, None),
+                ],
+                http_probes: vec![HttpProbe {
+                    url: String::from("https://www.example.com/"),
+                    status: Some(200),
+                    title: Some(String::from("Example")),
+                    server: Some(String::from("nginx")),
+                    location: None,
+                    content_length: Some(1256),
+                    error: None,
+                }],
+                tls: vec![TlsInfo {
+                    port: String::from("443"),
+                    chain: vec![Certificate {
+                        subject: String::from("CN=www.example.com"),
+                        common_name: Some(String::from("www.example.com")),
+                        sans: vec![String::from("www.example.com")],
+                        issuer: String::from("C=US, O=Let's Encrypt, CN=R3"),
+                        not_before: String::from("Jan  1 00:00:00 2024 GMT"),
+                        not_after: String::from("Mar 31 00:00:00 2024 GMT"),
+                        fingerprint_sha256: String::from("AB:CD"),
+                    }],
+                }],
+                nmap_xml: None,
+            },
+        ],
+    }
+}
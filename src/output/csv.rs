@@ -0,0 +1,177 @@
+use {
+    super::{HostResult, PortResult, Report},
+    crate::{args::CsvColumn, errors::Result},
+    csv::{Writer, WriterBuilder},
+    std::io::Write,
+};
+
+/// CSV writer with the given delimiter, fields are quoted only when needed
+pub fn writer<W: Write>(delimiter: u8, writer: W) -> Writer<W> {
+    WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(writer)
+}
+
+/// Write one row per host and port, hosts without open ports get a single
+/// row with empty port fields. Hosts that didn't resolve are left out
+pub fn write_csv<W: Write>(
+    report: &Report,
+    columns: &[CsvColumn],
+    delimiter: u8,
+    header: bool,
+    writer: W,
+) -> Result<()> {
+    let mut writer = self::writer(delimiter, writer);
+    if header {
+        writer.write_record(columns.iter().map(|column| column_name(*column)))?;
+    }
+    for host in report.hosts.iter().filter(|host| host.ip.is_some()) {
+        if host.ports.is_empty() {
+            writer.write_record(columns.iter().map(|column| cell(*column, host, None)))?;
+        }
+        for port in &host.ports {
+            writer.write_record(columns.iter().map(|column| cell(*column, host, Some(port))))?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[must_use]
+pub const fn column_name(column: CsvColumn) -> &'static str {
+    match column {
+        CsvColumn::Host => "HOST",
+        CsvColumn::Ip => "IP",
+        CsvColumn::Ips => "IPS",
+        CsvColumn::Port => "PORT",
+        CsvColumn::Protocol => "PROTOCOL",
+        CsvColumn::State => "STATE",
+        CsvColumn::Reason => "REASON",
+        CsvColumn::Service => "SERVICE",
+        CsvColumn::Version => "VERSION",
+        CsvColumn::Product => "PRODUCT",
+        CsvColumn::Os => "OS",
+        CsvColumn::Extrainfo => "EXTRAINFO",
+        CsvColumn::Tunnel => "TUNNEL",
+        CsvColumn::OsGuess => "OS_GUESS",
+        CsvColumn::Status => "STATUS",
+        CsvColumn::ScanError => "SCAN_ERROR",
+        CsvColumn::Ptr => "PTR",
+        CsvColumn::Asn => "ASN",
+        CsvColumn::AsOrg => "AS_ORG",
+        CsvColumn::Country => "COUNTRY",
+        CsvColumn::Cdn => "CDN",
+        CsvColumn::CloudProvider => "CLOUD_PROVIDER",
+        CsvColumn::CloudService => "CLOUD_SERVICE",
+        CsvColumn::CloudRegion => "CLOUD_REGION",
+    }
+}
+
+/// Value of a column, empty when it's unknown. Lists are joined with spaces
+fn cell(column: CsvColumn, host: &HostResult, port: Option<&PortResult>) -> String {
+    let service = port.and_then(|port| port.service.as_ref());
+    let value = match column {
+        CsvColumn::Host => Some(host.host.clone()),
+        CsvColumn::Ip => host.ip.clone(),
+        CsvColumn::Ips => Some(host.ips.join(" ")),
+        CsvColumn::Port => port.map(|port| port.port.to_string()),
+        CsvColumn::Protocol => port.map(|port| port.protocol.clone()),
+        CsvColumn::State => port.map(|port| port.state.clone()),
+        CsvColumn::Reason => port.map(|port| port.reason.clone()),
+        CsvColumn::Service => service.map(|service| service.name.clone()),
+        CsvColumn::Version => service.and_then(|service| service.version.clone()),
+        CsvColumn::Product => service.and_then(|service| service.product.clone()),
+        CsvColumn::Os => service.and_then(|service| service.ostype.clone()),
+        CsvColumn::Extrainfo => service.and_then(|service| service.extrainfo.clone()),
+        CsvColumn::Tunnel => service.and_then(|service| service.tunnel.clone()),
+        CsvColumn::OsGuess => host
+            .os
+            .as_ref()
+            .map(|os| format!("{} ({}%)", os.name, os.accuracy)),
+        CsvColumn::Status => Some(host.scan_status.clone()),
+        CsvColumn::ScanError => host.scan_error.clone(),
+        CsvColumn::Ptr => Some(host.ptr.join(" ")),
+        CsvColumn::Asn => host.asn.as_ref().map(|asn| asn.name()),
+        CsvColumn::AsOrg => host.asn.as_ref().map(|asn| asn.org.clone()),
+        CsvColumn::Country => host.asn.as_ref().and_then(|asn| asn.country.clone()),
+        CsvColumn::Cdn => host.cdn.clone(),
+        CsvColumn::CloudProvider => host.cloud.as_ref().map(|cloud| cloud.provider.clone()),
+        CsvColumn::CloudService => host.cloud.as_ref().and_then(|cloud| cloud.service.clone()),
+        CsvColumn::CloudRegion => host.cloud.as_ref().and_then(|cloud| cloud.region.clone()),
+    };
+    value.unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::output::sample_report};
+
+    fn report() -> Report {
+        let mut report = sample_report();
+        let host = &mut report.hosts[1];
+        host.ports[1].service.as_mut().unwrap().extrainfo =
+            Some(String::from("Ubuntu, \"jammy\"\tLTS\nbuild"));
+        let mut without_ports = host.clone();
+        without_ports.host = String::from("closed.example.com");
+        without_ports.ports.clear();
+        report.hosts.push(without_ports);
+        report
+    }
+
+    fn csv(columns: &[CsvColumn], delimiter: u8, header: bool) -> String {
+        let mut output = Vec::new();
+        write_csv(&report(), columns, delimiter, header, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn selected_columns() {
+        assert_eq!(
+            csv(
+                &[
+                    CsvColumn::Host,
+                    CsvColumn::Port,
+                    CsvColumn::Service,
+                    CsvColumn::OsGuess,
+                    CsvColumn::Asn
+                ],
+                b',',
+                true
+            ),
+            "HOST,PORT,SERVICE,OS_GUESS,ASN\n\
+             www.example.com,22,ssh,Linux 5.4 (95%),AS15133\n\
+             www.example.com,443,http,Linux 5.4 (95%),AS15133\n\
+             www.example.com,8080,,Linux 5.4 (95%),AS15133\n\
+             closed.example.com,,,Linux 5.4 (95%),AS15133\n"
+        );
+        assert_eq!(
+            csv(&[CsvColumn::Ips, CsvColumn::CloudRegion], b',', false),
+            "93.184.216.34 93.184.216.35,us-east-1\n".repeat(4)
+        );
+    }
+
+    #[test]
+    fn fields_are_quoted_when_needed() {
+        let columns = [CsvColumn::Port, CsvColumn::Extrainfo, CsvColumn::Product];
+        assert_eq!(
+            csv(&columns, b',', true),
+            "PORT,EXTRAINFO,PRODUCT\n\
+             22,,OpenSSH\n\
+             443,\"Ubuntu, \"\"jammy\"\"\tLTS\nbuild\",nginx\n\
+             8080,,\n\
+             ,,\n"
+        );
+        assert_eq!(
+            csv(&columns, parse_tab(), true),
+            "PORT\tEXTRAINFO\tPRODUCT\n\
+             22\t\tOpenSSH\n\
+             443\t\"Ubuntu, \"\"jammy\"\"\tLTS\nbuild\"\tnginx\n\
+             8080\t\t\n\
+             \t\t\n"
+        );
+    }
+
+    fn parse_tab() -> u8 {
+        crate::misc::parse_delimiter("tab").unwrap()
+    }
+}
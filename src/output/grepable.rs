@@ -0,0 +1,93 @@
+use {
+    super::{HostResult, PortResult, Report},
+    crate::errors::Result,
+    std::io::Write,
+};
+
+/// Write the results in the Nmap grepable format (-oG): a `Status` line and,
+/// when there are open ports, a `Ports` line for every host and IP
+pub fn write_grepable<W: Write>(report: &Report, mut writer: W) -> Result<()> {
+    writeln!(
+        writer,
+        "# Unimap {} scan results, generated at {}",
+        report.unimap_version, report.generated_at
+    )?;
+    let mut resolved = 0;
+    for (host, ip) in report
+        .hosts
+        .iter()
+        .filter_map(|host| host.ip.as_ref().map(|ip| (host, ip)))
+    {
+        resolved += 1;
+        let prefix = format!("Host: {ip} ({})", host.host);
+        writeln!(writer, "{prefix}\tStatus: {}", status(host))?;
+        if !host.ports.is_empty() {
+            let ports: Vec<String> = host.ports.iter().map(port_entry).collect();
+            write!(writer, "{prefix}\tPorts: {}", ports.join(", "))?;
+            if let Some(os) = &host.os {
+                write!(writer, "\tOS: {}", os.name)?;
+            }
+            writeln!(writer)?;
+        }
+    }
+    writeln!(writer, "# Unimap done: {resolved} hosts with an IP address")?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn status(host: &HostResult) -> &'static str {
+    match host.scan_status.as_str() {
+        "ok" | "timeout" => "Up",
+        "not_scanned" => "Unknown",
+        _ => "Down",
+    }
+}
+
+/// `port/state/protocol/owner/service/rpc_info/version/`, slashes inside the
+/// fields are replaced by `|` like Nmap does, and line breaks and tabs by spaces
+fn port_entry(port: &PortResult) -> String {
+    let (service, version) = port
+        .service
+        .as_ref()
+        .map_or_else(Default::default, |service| {
+            (service.name.clone(), service.description())
+        });
+    format!(
+        "{}/{}/{}//{}//{}/",
+        port.port,
+        field(&port.state),
+        field(&port.protocol),
+        field(&service),
+        field(&version)
+    )
+}
+
+fn field(value: &str) -> String {
+    value.replace('/', "|").replace(['\n', '\r', '\t'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::output::sample_report};
+
+    #[test]
+    fn fields_stay_on_the_port_entry() {
+        let mut report = sample_report();
+        let service = report.hosts[1].ports[0].service.as_mut().unwrap();
+        service.name = String::from("ssh|x\ny");
+        service.extrainfo = Some(String::from("protocol 2.0/3.0\ttest"));
+
+        let mut output = Vec::new();
+        write_grepable(&report, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "# Unimap 0.7.0 scan results, generated at 2024-01-01T00:00:00+00:00\n\
+             Host: 93.184.216.34 (www.example.com)\tStatus: Up\n\
+             Host: 93.184.216.34 (www.example.com)\tPorts: \
+             22/open/tcp//ssh|x y//OpenSSH 8.9p1 (protocol 2.0|3.0 test)/, \
+             443/open/tcp//http//nginx 1.18.0 (Ubuntu)/, \
+             8080/open/tcp/////\tOS: Linux 5.4\n\
+             # Unimap done: 1 hosts with an IP address\n"
+        );
+    }
+}
@@ -0,0 +1,356 @@
+use {
+    super::{escape, HostResult, Report},
+    crate::errors::Result,
+    std::{
+        collections::{BTreeMap, HashMap},
+        fmt::Write as _,
+        io::Write,
+        path::Path,
+    },
+};
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; color: #222; }
+h1, h2, h3 { color: #1a4d7a; }
+table { border-collapse: collapse; margin-bottom: 1em; }
+th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: left; vertical-align: top; }
+th { background: #eef3f8; }
+#hosts th { cursor: pointer; }
+#hosts th:after { content: ' \\2195'; color: #999; }
+.stats td:first-child { font-weight: bold; }
+.failure { color: #b00020; }
+.ip { border-top: 2px solid #1a4d7a; margin-top: 2em; }
+input { padding: 4px; width: 30em; margin-bottom: 1em; }
+";
+
+const SCRIPT: &str = "
+function filterHosts(value) {
+  value = value.toLowerCase();
+  for (const row of document.querySelectorAll('#hosts tbody tr')) {
+    row.style.display = row.textContent.toLowerCase().includes(value) ? '' : 'none';
+  }
+}
+function sortHosts(column) {
+  const table = document.getElementById('hosts');
+  const body = table.tBodies[0];
+  const ascending = table.dataset.column != column || table.dataset.order != 'asc';
+  const rows = Array.from(body.rows);
+  rows.sort(function (a, b) {
+    const x = a.cells[column].textContent, y = b.cells[column].textContent;
+    const order = x.localeCompare(y, undefined, { numeric: true });
+    return ascending ? order : -order;
+  });
+  rows.forEach(function (row) { body.appendChild(row); });
+  table.dataset.column = column;
+  table.dataset.order = ascending ? 'asc' : 'desc';
+}
+";
+
+/// Number of services listed in the summary
+const TOP_SERVICES: usize = 10;
+
+/// Links to the Nmap XML file of every IP scanned by this run, relative to
+/// the directory of the report when the logs are inside it
+#[must_use]
+pub fn xml_links(report: &Report, html_path: &str) -> HashMap<String, String> {
+    let base = Path::new(html_path)
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."))
+        .canonicalize()
+        .ok();
+    report
+        .hosts
+        .iter()
+        .filter_map(|host| Some((host.ip.as_ref()?, host.nmap_xml.as_ref()?)))
+        .filter_map(|(ip, xml)| {
+            let xml = Path::new(xml).canonicalize().ok()?;
+            let link = match base.as_ref().and_then(|base| xml.strip_prefix(base).ok()) {
+                Some(relative) => relative.to_string_lossy().replace('\\', "/"),
+                None => format!("file://{}", xml.display()),
+            };
+            Some((ip.clone(), link))
+        })
+        .collect()
+}
+
+/// Write a single offline HTML file with the summary of the run, a sortable
+/// and filterable table of hosts and a section for every IP
+pub fn write_html<W: Write>(
+    report: &Report,
+    xml_links: &HashMap<String, String>,
+    mut writer: W,
+) -> Result<()> {
+    let mut by_ip: BTreeMap<&str, Vec<&HostResult>> = BTreeMap::new();
+    for host in &report.hosts {
+        if let Some(ip) = &host.ip {
+            by_ip.entry(ip).or_default().push(host);
+        }
+    }
+
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Unimap report</title>\n<style>{STYLE}</style>\n<script>{SCRIPT}</script>\n</head>\n<body>\n<h1>Unimap report</h1>\n<p>Generated at {} by unimap {}</p>\n",
+        escape(&report.generated_at),
+        escape(&report.unimap_version)
+    );
+    summary(&mut html, report, &by_ip);
+    hosts_table(&mut html, report);
+    for (ip, hosts) in &by_ip {
+        ip_section(&mut html, ip, hosts, xml_links.get(*ip));
+    }
+    html.push_str("</body>\n</html>\n");
+
+    writer.write_all(html.as_bytes())?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn summary(html: &mut String, report: &Report, by_ip: &BTreeMap<&str, Vec<&HostResult>>) {
+    // Ports and services belong to the IP, count them once per IP
+    let ips: Vec<&HostResult> = by_ip
+        .values()
+        .filter_map(|hosts| hosts.first().copied())
+        .collect();
+    let open_ports: usize = ips.iter().map(|host| host.ports.len()).sum();
+    let failed = ips.iter().filter(|host| is_failure(host)).count();
+    let mut services: HashMap<&str, usize> = HashMap::new();
+    for port in ips.iter().flat_map(|host| &host.ports) {
+        let name = port
+            .service
+            .as_ref()
+            .map_or("unknown", |service| service.name.as_str());
+        *services.entry(name).or_default() += 1;
+    }
+    let mut services: Vec<(&str, usize)> = services.into_iter().collect();
+    services.sort_by(|(a_name, a_count), (b_name, b_count)| {
+        b_count.cmp(a_count).then_with(|| a_name.cmp(b_name))
+    });
+
+    html.push_str("<h2>Summary</h2>\n<table class=\"stats\">\n");
+    for (name, value) in [
+        ("Targets", report.hosts.len()),
+        (
+            "Resolved targets",
+            report.hosts.iter().filter(|host| host.ip.is_some()).count(),
+        ),
+        ("Unique IPs", by_ip.len()),
+        ("Open ports", open_ports),
+        ("Failed scans", failed),
+    ] {
+        let _ = writeln!(html, "<tr><td>{name}</td><td>{value}</td></tr>");
+    }
+    html.push_str("</table>\n");
+
+    if !services.is_empty() {
+        html.push_str(
+            "<h3>Top services</h3>\n<table>\n<tr><th>Service</th><th>Open ports</th></tr>\n",
+        );
+        for (name, count) in services.iter().take(TOP_SERVICES) {
+            let _ = writeln!(html, "<tr><td>{}</td><td>{count}</td></tr>", escape(name));
+        }
+        html.push_str("</table>\n");
+    }
+}
+
+fn hosts_table(html: &mut String, report: &Report) {
+    html.push_str("<h2>Hosts</h2>\n<input type=\"search\" placeholder=\"Filter hosts, IPs, ports or services\" oninput=\"filterHosts(this.value)\">\n<table id=\"hosts\">\n<thead><tr>");
+    for (column, title) in [
+        "Host",
+        "IP",
+        "Open ports",
+        "Services",
+        "OS",
+        "Tags",
+        "Status",
+    ]
+    .iter()
+    .enumerate()
+    {
+        let _ = write!(html, "<th onclick=\"sortHosts({column})\">{title}</th>");
+    }
+    html.push_str("</tr></thead>\n<tbody>\n");
+    for host in &report.hosts {
+        let ip = host.ip.as_ref().map_or_else(String::new, |ip| {
+            format!("<a href=\"#{}\">{}</a>", anchor(ip), escape(ip))
+        });
+        let ports: Vec<String> = host
+            .ports
+            .iter()
+            .map(|port| port.port.to_string())
+            .collect();
+        let mut services: Vec<&str> = host
+            .ports
+            .iter()
+            .filter_map(|port| port.service.as_ref())
+            .map(|service| service.name.as_str())
+            .collect();
+        services.sort_unstable();
+        services.dedup();
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td>{ip}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td>{}</tr>",
+            escape(&host.host),
+            ports.join(", "),
+            escape(&services.join(", ")),
+            escape(&os_string(host)),
+            escape(&tags(host).join(", ")),
+            status_cell(host)
+        );
+    }
+    html.push_str("</tbody>\n</table>\n");
+}
+
+fn ip_section(html: &mut String, ip: &str, hosts: &[&HostResult], xml_link: Option<&String>) {
+    // All the hosts of the IP share the scan results
+    let first = hosts[0];
+    let _ = writeln!(
+        html,
+        "<div class=\"ip\" id=\"{}\">\n<h2>{}</h2>",
+        anchor(ip),
+        escape(ip)
+    );
+
+    html.push_str("<table class=\"stats\">\n");
+    let names: Vec<&str> = hosts.iter().map(|host| host.host.as_str()).collect();
+    detail_row(html, "Hostnames", &names.join("\n"));
+    if !first.ptr.is_empty() {
+        detail_row(html, "PTR", &first.ptr.join("\n"));
+    }
+    if let Some(asn) = &first.asn {
+        let country = asn
+            .country
+            .as_ref()
+            .map_or_else(String::new, |country| format!(" ({country})"));
+        detail_row(html, "ASN", &format!("{} {}{country}", asn.name(), asn.org));
+    }
+    let tags = tags(first);
+    if !tags.is_empty() {
+        detail_row(html, "Tags", &tags.join(", "));
+    }
+    detail_row(html, "OS", &os_string(first));
+    let _ = writeln!(html, "<tr><td>Status</td>{}</tr>", status_cell(first));
+    if let Some(link) = xml_link {
+        let _ = writeln!(
+            html,
+            "<tr><td>Nmap XML</td><td><a href=\"{}\">{}.xml</a></td></tr>",
+            escape(link),
+            escape(ip)
+        );
+    }
+    html.push_str("</table>\n");
+
+    if !first.ports.is_empty() {
+        html.push_str("<h3>Ports</h3>\n<table>\n<tr><th>Port</th><th>Protocol</th><th>Service</th><th>Product</th><th>Version</th><th>Extra info</th></tr>\n");
+        for port in &first.ports {
+            let service = port.service.clone().unwrap_or_default();
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                port.port,
+                escape(&port.protocol),
+                escape(&service.name),
+                escape(service.product.as_deref().unwrap_or_default()),
+                escape(service.version.as_deref().unwrap_or_default()),
+                escape(service.extrainfo.as_deref().unwrap_or_default())
+            );
+        }
+        html.push_str("</table>\n");
+    }
+
+    if hosts.iter().any(|host| !host.http_probes.is_empty()) {
+        html.push_str("<h3>HTTP</h3>\n<table>\n<tr><th>Host</th><th>URL</th><th>Status</th><th>Title</th><th>Server</th><th>Location</th></tr>\n");
+        for host in hosts {
+            for probe in &host.http_probes {
+                let status = probe.status.map_or_else(
+                    || probe.error.clone().unwrap_or_default(),
+                    |status| status.to_string(),
+                );
+                let _ = writeln!(
+                    html,
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                    escape(&host.host),
+                    escape(&probe.url),
+                    escape(&status),
+                    escape(probe.title.as_deref().unwrap_or_default()),
+                    escape(probe.server.as_deref().unwrap_or_default()),
+                    escape(probe.location.as_deref().unwrap_or_default())
+                );
+            }
+        }
+        html.push_str("</table>\n");
+    }
+
+    let certificates: Vec<_> = first
+        .tls
+        .iter()
+        .filter_map(|tls| tls.leaf().map(|leaf| (&tls.port, leaf)))
+        .collect();
+    if !certificates.is_empty() {
+        html.push_str("<h3>TLS certificates</h3>\n<table>\n<tr><th>Port</th><th>Subject</th><th>SANs</th><th>Issuer</th><th>Not after</th><th>SHA256</th></tr>\n");
+        for (port, leaf) in certificates {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape(port),
+                escape(&leaf.subject),
+                escape(&leaf.sans.join("\n")).replace('\n', "<br>"),
+                escape(&leaf.issuer),
+                escape(&leaf.not_after),
+                escape(&leaf.fingerprint_sha256)
+            );
+        }
+        html.push_str("</table>\n");
+    }
+    html.push_str("</div>\n");
+}
+
+fn detail_row(html: &mut String, name: &str, value: &str) {
+    let _ = writeln!(
+        html,
+        "<tr><td>{name}</td><td>{}</td></tr>",
+        escape(value).replace('\n', "<br>")
+    );
+}
+
+fn status_cell(host: &HostResult) -> String {
+    let status = host.scan_error.as_ref().map_or_else(
+        || host.scan_status.clone(),
+        |error| format!("{}: {error}", host.scan_status),
+    );
+    if is_failure(host) {
+        format!("<td class=\"failure\">{}</td>", escape(&status))
+    } else {
+        format!("<td>{}</td>", escape(&status))
+    }
+}
+
+fn is_failure(host: &HostResult) -> bool {
+    !matches!(host.scan_status.as_str(), "ok" | "not_scanned")
+}
+
+fn os_string(host: &HostResult) -> String {
+    host.os
+        .as_ref()
+        .map_or_else(String::new, |os| format!("{} ({}%)", os.name, os.accuracy))
+}
+
+/// CDN, cloud and ASN labels of the host
+fn tags(host: &HostResult) -> Vec<String> {
+    let mut tags = Vec::new();
+    if let Some(cdn) = &host.cdn {
+        tags.push(format!("CDN: {cdn}"));
+    }
+    if let Some(cloud) = &host.cloud {
+        tags.push(format!("Cloud: {}", cloud.provider));
+    }
+    if let Some(asn) = &host.asn {
+        tags.push(asn.name());
+    }
+    tags
+}
+
+fn anchor(ip: &str) -> String {
+    format!("ip-{}", ip.replace([':', '.'], "-"))
+}
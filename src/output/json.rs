@@ -0,0 +1,178 @@
+use {
+    super::{HostResult, PortResult, Report, ServiceResult},
+    crate::{args::JsonlMode, errors::Result},
+    std::io::Write,
+};
+
+/// A line of `--jsonl port`, hosts without open ports get a single line
+/// with the port fields set to null
+#[derive(Serialize)]
+struct PortLine<'a> {
+    host: &'a str,
+    ip: Option<&'a str>,
+    scan_status: &'a str,
+    port: Option<u16>,
+    protocol: Option<&'a str>,
+    state: Option<&'a str>,
+    reason: Option<&'a str>,
+    service: Option<&'a ServiceResult>,
+}
+
+impl<'a> PortLine<'a> {
+    fn new(host: &'a HostResult, port: Option<&'a PortResult>) -> Self {
+        Self {
+            host: &host.host,
+            ip: host.ip.as_deref(),
+            scan_status: &host.scan_status,
+            port: port.map(|port| port.port),
+            protocol: port.map(|port| port.protocol.as_str()),
+            state: port.map(|port| port.state.as_str()),
+            reason: port.map(|port| port.reason.as_str()),
+            service: port.and_then(|port| port.service.as_ref()),
+        }
+    }
+}
+
+pub fn write_json<W: Write>(report: &Report, mut writer: W) -> Result<()> {
+    serde_json::to_writer_pretty(&mut writer, report)?;
+    writeln!(writer)?;
+    Ok(())
+}
+
+pub fn write_jsonl<W: Write>(report: &Report, mode: JsonlMode, mut writer: W) -> Result<()> {
+    for host in &report.hosts {
+        match mode {
+            JsonlMode::Host => {
+                serde_json::to_writer(&mut writer, host)?;
+                writeln!(writer)?;
+            }
+            JsonlMode::Port if host.ports.is_empty() => {
+                serde_json::to_writer(&mut writer, &PortLine::new(host, None))?;
+                writeln!(writer)?;
+            }
+            JsonlMode::Port => {
+                for port in &host.ports {
+                    serde_json::to_writer(&mut writer, &PortLine::new(host, Some(port)))?;
+                    writeln!(writer)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::output::sample_report,
+        serde_json::{json, Value},
+    };
+
+    fn lines(output: &[u8]) -> Vec<Value> {
+        std::str::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn json_schema() {
+        let mut output = Vec::new();
+        write_json(&sample_report(), &mut output).unwrap();
+        let report: Value = serde_json::from_slice(&output).unwrap();
+
+        assert_eq!(
+            report,
+            json!({
+                "schema_version": 1,
+                "unimap_version": "0.7.0",
+                "generated_at": "2024-01-01T00:00:00+00:00",
+                "hosts": [
+                    {
+                        "host": "missing.example.com", "ip": null, "ips": [], "ptr": [], "asn": null,
+                        "cdn": null, "cloud": null, "scan_status": "not_scanned", "scan_error": null,
+                        "os": null, "ports": [], "http_probes": [], "tls": []
+                    },
+                    {
+                        "host": "www.example.com",
+                        "ip": "93.184.216.34",
+                        "ips": ["93.184.216.34", "93.184.216.35"],
+                        "ptr": ["edge.example.net"],
+                        "asn": {"number": 15133, "org": "EDGECAST", "country": "US"},
+                        "cdn": null,
+                        "cloud": {"provider": "AWS", "region": "us-east-1", "service": "EC2"},
+                        "scan_status": "ok",
+                        "scan_error": null,
+                        "os": {"name": "Linux 5.4", "accuracy": 95},
+                        "ports": [
+                            {
+                                "port": 22, "protocol": "tcp", "state": "open", "reason": "syn-ack",
+                                "service": {"name": "ssh", "product": "OpenSSH", "version": "8.9p1", "extrainfo": null,
+                                            "ostype": null, "tunnel": null, "method": "probed", "confidence": 10}
+                            },
+                            {
+                                "port": 443, "protocol": "tcp", "state": "open", "reason": "syn-ack",
+                                "service": {"name": "http", "product": "nginx", "version": "1.18.0",
+                                            "extrainfo": "Ubuntu", "ostype": null, "tunnel": "ssl",
+                                            "method": "probed", "confidence": 10}
+                            },
+                            {"port": 8080, "protocol": "tcp", "state": "open", "reason": "syn-ack", "service": null}
+                        ],
+                        "http_probes": [
+                            {"url": "https://www.example.com/", "status": 200, "title": "Example", "server": "nginx",
+                             "location": null, "content_length": 1256, "error": null}
+                        ],
+                        "tls": [
+                            {"port": "443", "chain": [{"subject": "CN=www.example.com", "common_name": "www.example.com",
+                             "sans": ["www.example.com"], "issuer": "C=US, O=Let's Encrypt, CN=R3",
+                             "not_before": "Jan  1 00:00:00 2024 GMT", "not_after": "Mar 31 00:00:00 2024 GMT",
+                             "fingerprint_sha256": "AB:CD"}]}
+                        ]
+                    }
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn jsonl_host_lines_are_the_hosts_objects() {
+        let report = sample_report();
+        let mut output = Vec::new();
+        write_jsonl(&report, JsonlMode::Host, &mut output).unwrap();
+
+        let expected: Vec<Value> = report
+            .hosts
+            .iter()
+            .map(|host| serde_json::to_value(host).unwrap())
+            .collect();
+        assert_eq!(lines(&output), expected);
+        assert_eq!(expected[1]["ports"].as_array().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn jsonl_port_lines() {
+        let mut output = Vec::new();
+        write_jsonl(&sample_report(), JsonlMode::Port, &mut output).unwrap();
+        let lines = lines(&output);
+
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[0],
+            json!({"host": "missing.example.com", "ip": null, "scan_status": "not_scanned", "port": null,
+                   "protocol": null, "state": null, "reason": null, "service": null})
+        );
+        assert_eq!(
+            lines[1],
+            json!({"host": "www.example.com", "ip": "93.184.216.34", "scan_status": "ok", "port": 22,
+                   "protocol": "tcp", "state": "open", "reason": "syn-ack",
+                   "service": {"name": "ssh", "product": "OpenSSH", "version": "8.9p1", "extrainfo": null,
+                               "ostype": null, "tunnel": null, "method": "probed", "confidence": 10}})
+        );
+        assert_eq!(lines[2]["port"], 443);
+        assert_eq!(lines[2]["service"]["tunnel"], "ssl");
+        assert_eq!(lines[3]["port"], 8080);
+        assert_eq!(lines[3]["service"], Value::Null);
+    }
+}
@@ -0,0 +1,92 @@
+use {
+    super::{HostResult, Report},
+    crate::errors::Result,
+    std::io::Write,
+};
+
+/// Write the results as a Markdown table, one row per host
+pub fn write_markdown<W: Write>(report: &Report, mut writer: W) -> Result<()> {
+    writeln!(
+        writer,
+        "| Host | IP | Open ports | Services | OS | Status |"
+    )?;
+    writeln!(writer, "| --- | --- | --- | --- | --- | --- |")?;
+    for host in report.hosts.iter().filter(|host| host.ip.is_some()) {
+        let ports: Vec<String> = host
+            .ports
+            .iter()
+            .map(|port| port.port.to_string())
+            .collect();
+        writeln!(
+            writer,
+            "| {} | {} | {} | {} | {} | {} |",
+            cell(&host.host),
+            cell(host.ip.as_deref().unwrap_or_default()),
+            ports.join(", "),
+            services(host),
+            cell(
+                &host
+                    .os
+                    .as_ref()
+                    .map_or_else(String::new, |os| format!("{} ({}%)", os.name, os.accuracy))
+            ),
+            cell(&host.scan_error.as_ref().map_or_else(
+                || host.scan_status.clone(),
+                |error| format!("{}: {error}", host.scan_status)
+            ))
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// `port/service description` of every open port, one per line of the cell
+fn services(host: &HostResult) -> String {
+    host.ports
+        .iter()
+        .filter_map(|port| {
+            port.service.as_ref().map(|service| {
+                let description = service.description();
+                if description.is_empty() {
+                    cell(&format!("{}/{}", port.port, service.name))
+                } else {
+                    cell(&format!("{}/{} {description}", port.port, service.name))
+                }
+            })
+        })
+        .collect::<Vec<String>>()
+        .join("<br>")
+}
+
+/// Escape the characters that would break the table
+fn cell(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('\\', "\\\\")
+        .replace('|', "\\|")
+        .replace('<', "&lt;")
+        .replace('\n', " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::output::sample_report};
+
+    #[test]
+    fn cells_are_escaped() {
+        let mut report = sample_report();
+        let service = report.hosts[1].ports[0].service.as_mut().unwrap();
+        service.name = String::from("ssh|x\ny");
+        service.extrainfo = Some(String::from("<b>A&amp;B</b> \\"));
+
+        let mut output = Vec::new();
+        write_markdown(&report, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "| Host | IP | Open ports | Services | OS | Status |\n\
+             | --- | --- | --- | --- | --- | --- |\n\
+             | www.example.com | 93.184.216.34 | 22, 443, 8080 | \
+             22/ssh\\|x y OpenSSH 8.9p1 (&lt;b>A&amp;amp;B&lt;/b> \\\\)<br>\
+             443/http nginx 1.18.0 (Ubuntu) | Linux 5.4 (95%) | ok |\n"
+        );
+    }
+}
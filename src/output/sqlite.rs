@@ -0,0 +1,477 @@
+use {
+    super::{HostResult, OsGuess, PortResult, Report, ServiceResult, SCHEMA_VERSION},
+    crate::{
+        asn::AsnInfo,
+        cloud::CloudInfo,
+        errors::{Result, ResultExt},
+        tls::{Certificate, TlsInfo},
+    },
+    failure::bail,
+    rusqlite::{params, Connection},
+    std::collections::HashMap,
+};
+
+/// Version of the database schema, stored as `PRAGMA user_version`
+pub const DATABASE_VERSION: i64 = 2;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    started_at TEXT NOT NULL,
+    finished_at TEXT NOT NULL,
+    unimap_version TEXT NOT NULL,
+    schema_version INTEGER NOT NULL,
+    command_line TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS targets (
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    host TEXT NOT NULL,
+    ip TEXT,
+    scan_status TEXT NOT NULL,
+    scan_error TEXT,
+    cdn TEXT,
+    PRIMARY KEY (run_id, host)
+);
+CREATE TABLE IF NOT EXISTS resolutions (
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    host TEXT NOT NULL,
+    ip TEXT NOT NULL,
+    scanned INTEGER NOT NULL,
+    PRIMARY KEY (run_id, host, ip)
+);
+CREATE TABLE IF NOT EXISTS ips (
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    ip TEXT NOT NULL,
+    ptr TEXT,
+    asn INTEGER,
+    as_org TEXT,
+    country TEXT,
+    cloud_provider TEXT,
+    cloud_service TEXT,
+    cloud_region TEXT,
+    os TEXT,
+    os_accuracy INTEGER,
+    PRIMARY KEY (run_id, ip)
+);
+CREATE TABLE IF NOT EXISTS ports (
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    ip TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    protocol TEXT NOT NULL,
+    state TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    PRIMARY KEY (run_id, ip, protocol, port)
+);
+CREATE TABLE IF NOT EXISTS services (
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    ip TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    protocol TEXT NOT NULL,
+    name TEXT NOT NULL,
+    product TEXT,
+    version TEXT,
+    extrainfo TEXT,
+    ostype TEXT,
+    tunnel TEXT,
+    method TEXT NOT NULL,
+    confidence INTEGER NOT NULL,
+    PRIMARY KEY (run_id, ip, protocol, port)
+);
+CREATE TABLE IF NOT EXISTS certificates (
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    ip TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    subject TEXT NOT NULL,
+    common_name TEXT,
+    sans TEXT,
+    issuer TEXT NOT NULL,
+    not_before TEXT NOT NULL,
+    not_after TEXT NOT NULL,
+    fingerprint_sha256 TEXT NOT NULL,
+    PRIMARY KEY (run_id, ip, port, position)
+);
+CREATE INDEX IF NOT EXISTS targets_host ON targets (host);
+CREATE INDEX IF NOT EXISTS resolutions_ip ON resolutions (ip);
+CREATE INDEX IF NOT EXISTS ports_port ON ports (port);
+";
+
+/// Append the results of the run to the SQLite database at `path`, creating
+/// it if needed. Returns the ID of the new run
+pub fn write_sqlite(report: &Report, path: &str, started_at: &str) -> Result<i64> {
+    let mut connection =
+        Connection::open(path).with_context(|_| format!("Can't open the database {path}"))?;
+    let version: i64 = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version > DATABASE_VERSION {
+        bail!(
+            "The database {} uses the schema version {}, newer than the {} supported by this unimap version",
+            path,
+            version,
+            DATABASE_VERSION
+        )
+    }
+    connection.execute_batch(SCHEMA)?;
+    connection.pragma_update(None, "user_version", DATABASE_VERSION)?;
+
+    let transaction = connection.transaction()?;
+    transaction.execute(
+        "INSERT INTO runs (started_at, finished_at, unimap_version, schema_version, command_line) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            started_at,
+            report.generated_at,
+            report.unimap_version,
+            SCHEMA_VERSION,
+            std::env::args().collect::<Vec<String>>().join(" ")
+        ],
+    )?;
+    let run_id = transaction.last_insert_rowid();
+    {
+        let mut target = transaction.prepare(
+            "INSERT OR REPLACE INTO targets (run_id, host, ip, scan_status, scan_error, cdn) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?;
+        let mut resolution = transaction.prepare(
+            "INSERT OR REPLACE INTO resolutions (run_id, host, ip, scanned) VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        let mut ip = transaction.prepare(
+            "INSERT OR REPLACE INTO ips (run_id, ip, ptr, asn, as_org, country, cloud_provider, cloud_service, cloud_region, os, os_accuracy) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        )?;
+        let mut port = transaction.prepare(
+            "INSERT OR REPLACE INTO ports (run_id, ip, port, protocol, state, reason) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?;
+        let mut service = transaction.prepare(
+            "INSERT OR REPLACE INTO services (run_id, ip, port, protocol, name, product, version, extrainfo, ostype, tunnel, method, confidence) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+        )?;
+        let mut certificate = transaction.prepare(
+            "INSERT OR REPLACE INTO certificates (run_id, ip, port, position, subject, common_name, sans, issuer, not_before, not_after, fingerprint_sha256) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        )?;
+
+        for host in &report.hosts {
+            target.execute(params![
+                run_id,
+                host.host,
+                host.ip,
+                host.scan_status,
+                host.scan_error,
+                host.cdn
+            ])?;
+            for address in &host.ips {
+                resolution.execute(params![
+                    run_id,
+                    host.host,
+                    address,
+                    host.ip.as_ref() == Some(address)
+                ])?;
+            }
+
+            // Everything below belongs to the scanned IP, shared by its hosts
+            let address = match &host.ip {
+                Some(address) => address,
+                None => continue,
+            };
+            ip.execute(params![
+                run_id,
+                address,
+                Some(host.ptr.join(" ")).filter(|ptr| !ptr.is_empty()),
+                host.asn.as_ref().map(|asn| asn.number),
+                host.asn.as_ref().map(|asn| &asn.org),
+                host.asn.as_ref().and_then(|asn| asn.country.as_ref()),
+                host.cloud.as_ref().map(|cloud| &cloud.provider),
+                host.cloud.as_ref().and_then(|cloud| cloud.service.as_ref()),
+                host.cloud.as_ref().and_then(|cloud| cloud.region.as_ref()),
+                host.os.as_ref().map(|os| &os.name),
+                host.os.as_ref().map(|os| os.accuracy)
+            ])?;
+            for open_port in &host.ports {
+                port.execute(params![
+                    run_id,
+                    address,
+                    open_port.port,
+                    open_port.protocol,
+                    open_port.state,
+                    open_port.reason
+                ])?;
+                if let Some(found) = &open_port.service {
+                    service.execute(params![
+                        run_id,
+                        address,
+                        open_port.port,
+                        open_port.protocol,
+                        found.name,
+                        found.product,
+                        found.version,
+                        found.extrainfo,
+                        found.ostype,
+                        found.tunnel,
+                        found.method,
+                        found.confidence
+                    ])?;
+                }
+            }
+            for tls in &host.tls {
+                for (position, found) in tls.chain.iter().enumerate() {
+                    certificate.execute(params![
+                        run_id,
+                        address,
+                        tls.port.parse::<u16>().unwrap_or_default(),
+                        position,
+                        found.subject,
+                        found.common_name,
+                        Some(found.sans.join(" ")).filter(|sans| !sans.is_empty()),
+                        found.issuer,
+                        found.not_before,
+                        found.not_after,
+                        found.fingerprint_sha256
+                    ])?;
+                }
+            }
+        }
+    }
+    transaction.commit()?;
+    Ok(run_id)
+}
+
+/// Whether the file is a SQLite database, from its header
+#[must_use]
+pub fn is_database(path: &str) -> bool {
+    let mut header = [0; 16];
+    std::fs::File::open(path)
+        .and_then(|mut file| std::io::Read::read_exact(&mut file, &mut header))
+        .is_ok()
+        && &header == b"SQLite format 3\0"
+}
+
+/// ID of the latest run of the database, or of the latest one before `before`
+pub fn latest_run(path: &str, before: Option<i64>) -> Result<i64> {
+    let connection =
+        Connection::open(path).with_context(|_| format!("Can't open the database {path}"))?;
+    let run: Option<i64> = connection.query_row(
+        "SELECT MAX(id) FROM runs WHERE id < ?1",
+        params![before.unwrap_or(i64::MAX)],
+        |row| row.get(0),
+    )?;
+    match run {
+        Some(run) => Ok(run),
+        None => bail!("No previous run found in the database {}", path),
+    }
+}
+
+/// Load a run of the database as a report. The HTTP probes are not stored,
+/// so they are empty
+pub fn read_sqlite(path: &str, run_id: i64) -> Result<Report> {
+    let connection =
+        Connection::open(path).with_context(|_| format!("Can't open the database {path}"))?;
+    let (generated_at, unimap_version, schema_version) = connection
+        .query_row(
+            "SELECT finished_at, unimap_version, schema_version FROM runs WHERE id = ?1",
+            params![run_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .with_context(|_| format!("Run {run_id} not found in the database {path}"))?;
+
+    let mut resolutions: HashMap<String, Vec<String>> = HashMap::new();
+    let mut statement = connection
+        .prepare("SELECT host, ip FROM resolutions WHERE run_id = ?1 ORDER BY scanned DESC, ip")?;
+    let mut rows = statement.query(params![run_id])?;
+    while let Some(row) = rows.next()? {
+        resolutions
+            .entry(row.get(0)?)
+            .or_default()
+            .push(row.get(1)?);
+    }
+
+    let mut ports: HashMap<String, Vec<PortResult>> = HashMap::new();
+    let mut statement = connection.prepare(
+        "SELECT p.ip, p.port, p.protocol, p.state, p.reason, s.name, s.product, s.version, s.extrainfo, s.ostype, s.tunnel, s.method, s.confidence
+        FROM ports p LEFT JOIN services s ON s.run_id = p.run_id AND s.ip = p.ip AND s.port = p.port AND s.protocol = p.protocol
+        WHERE p.run_id = ?1 ORDER BY p.ip, p.port",
+    )?;
+    let mut rows = statement.query(params![run_id])?;
+    while let Some(row) = rows.next()? {
+        let name: Option<String> = row.get(5)?;
+        let service = match name {
+            Some(name) => Some(ServiceResult {
+                name,
+                product: row.get(6)?,
+                version: row.get(7)?,
+                extrainfo: row.get(8)?,
+                ostype: row.get(9)?,
+                tunnel: row.get(10)?,
+                method: row.get(11)?,
+                confidence: row.get(12)?,
+            }),
+            None => None,
+        };
+        ports.entry(row.get(0)?).or_default().push(PortResult {
+            port: row.get(1)?,
+            protocol: row.get(2)?,
+            state: row.get(3)?,
+            reason: row.get(4)?,
+            service,
+        });
+    }
+
+    let mut certificates: HashMap<String, Vec<TlsInfo>> = HashMap::new();
+    let mut statement = connection.prepare(
+        "SELECT ip, port, subject, common_name, sans, issuer, not_before, not_after, fingerprint_sha256
+        FROM certificates WHERE run_id = ?1 ORDER BY ip, port, position",
+    )?;
+    let mut rows = statement.query(params![run_id])?;
+    while let Some(row) = rows.next()? {
+        let sans: Option<String> = row.get(4)?;
+        let found = Certificate {
+            subject: row.get(2)?,
+            common_name: row.get(3)?,
+            sans: sans
+                .map(|sans| sans.split(' ').map(str::to_string).collect())
+                .unwrap_or_default(),
+            issuer: row.get(5)?,
+            not_before: row.get(6)?,
+            not_after: row.get(7)?,
+            fingerprint_sha256: row.get(8)?,
+        };
+        let port = row.get::<_, u16>(1)?.to_string();
+        let chains = certificates.entry(row.get(0)?).or_default();
+        match chains.last_mut() {
+            Some(tls) if tls.port == port => tls.chain.push(found),
+            _ => chains.push(TlsInfo {
+                port,
+                chain: vec![found],
+            }),
+        }
+    }
+
+    let mut ips: HashMap<String, HostResult> = HashMap::new();
+    let mut statement = connection.prepare(
+        "SELECT ip, ptr, asn, as_org, country, cloud_provider, cloud_service, cloud_region, os, os_accuracy FROM ips WHERE run_id = ?1",
+    )?;
+    let mut rows = statement.query(params![run_id])?;
+    while let Some(row) = rows.next()? {
+        let ptr: Option<String> = row.get(1)?;
+        let asn: Option<u32> = row.get(2)?;
+        let cloud_provider: Option<String> = row.get(5)?;
+        let os: Option<String> = row.get(8)?;
+        let ip: String = row.get(0)?;
+        let details = HostResult {
+            ptr: ptr
+                .map(|ptr| ptr.split(' ').map(str::to_string).collect())
+                .unwrap_or_default(),
+            asn: match asn {
+                Some(number) => Some(AsnInfo {
+                    number,
+                    org: row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+                    country: row.get(4)?,
+                }),
+                None => None,
+            },
+            cloud: match cloud_provider {
+                Some(provider) => Some(CloudInfo {
+                    provider,
+                    service: row.get(6)?,
+                    region: row.get(7)?,
+                }),
+                None => None,
+            },
+            os: match os {
+                Some(name) => Some(OsGuess {
+                    name,
+                    accuracy: row.get::<_, Option<u8>>(9)?.unwrap_or_default(),
+                }),
+                None => None,
+            },
+            ports: ports.remove(&ip).unwrap_or_default(),
+            tls: certificates.remove(&ip).unwrap_or_default(),
+            ..HostResult::default()
+        };
+        ips.insert(ip, details);
+    }
+
+    let mut hosts = Vec::new();
+    let mut statement = connection.prepare(
+        "SELECT host, ip, scan_status, scan_error, cdn FROM targets WHERE run_id = ?1 ORDER BY host",
+    )?;
+    let mut rows = statement.query(params![run_id])?;
+    while let Some(row) = rows.next()? {
+        let host: String = row.get(0)?;
+        let ip: Option<String> = row.get(1)?;
+        let details = ip
+            .as_ref()
+            .and_then(|ip| ips.get(ip))
+            .cloned()
+            .unwrap_or_default();
+        hosts.push(HostResult {
+            ips: resolutions.remove(&host).unwrap_or_default(),
+            host,
+            ip,
+            scan_status: row.get(2)?,
+            scan_error: row.get(3)?,
+            cdn: row.get(4)?,
+            ..details
+        });
+    }
+
+    Ok(Report {
+        schema_version,
+        unimap_version,
+        generated_at,
+        hosts,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::output::sample_report};
+
+    fn database(name: &str) -> String {
+        let path =
+            std::env::temp_dir().join(format!("unimap-{name}-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path.display().to_string()
+    }
+
+    #[test]
+    fn reports_round_trip() {
+        let path = database("round-trip");
+        let mut report = sample_report();
+        let mut intermediate = report.hosts[1].tls[0].chain[0].clone();
+        intermediate.subject = String::from("C=US, O=Let's Encrypt, CN=R3");
+        intermediate.common_name = Some(String::from("R3"));
+        intermediate.sans.clear();
+        report.hosts[1].tls[0].chain.push(intermediate);
+        let mut alternative = report.hosts[1].tls[0].clone();
+        alternative.port = String::from("8443");
+        alternative.chain.truncate(1);
+        report.hosts[1].tls.push(alternative);
+
+        let run_id = write_sqlite(&report, &path, "2024-01-01T00:00:00+00:00").unwrap();
+        let read = read_sqlite(&path, run_id);
+        let _ = std::fs::remove_file(&path);
+        let read = read.unwrap();
+
+        // The HTTP probes are not stored
+        report.hosts[1].http_probes.clear();
+        assert_eq!(read.schema_version, report.schema_version);
+        assert_eq!(read.unimap_version, report.unimap_version);
+        assert_eq!(read.generated_at, report.generated_at);
+        assert_eq!(read.hosts, report.hosts);
+    }
+
+    #[test]
+    fn latest_run_is_the_newest_one() {
+        let path = database("latest-run");
+        let report = sample_report();
+        let runs: Vec<i64> = (0..3)
+            .map(|_| write_sqlite(&report, &path, "2024-01-01T00:00:00+00:00").unwrap())
+            .collect();
+        let latest = latest_run(&path, None);
+        let previous = latest_run(&path, Some(runs[2]));
+        let first = latest_run(&path, Some(runs[1]));
+        let none = latest_run(&path, Some(runs[0]));
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(latest.unwrap(), runs[2]);
+        assert_eq!(previous.unwrap(), runs[1]);
+        assert_eq!(first.unwrap(), runs[0]);
+        assert!(none.is_err());
+        assert!(runs[0] < runs[1] && runs[1] < runs[2]);
+    }
+}
@@ -0,0 +1,12 @@
+use {super::Report, crate::errors::Result, std::io::Write};
+
+/// Write a `HOST:PORT` line for every open port of every host
+pub fn write_urls<W: Write>(report: &Report, mut writer: W) -> Result<()> {
+    for host in &report.hosts {
+        for port in &host.ports {
+            writeln!(writer, "{}:{}", host.host, port.port)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
@@ -0,0 +1,282 @@
+use {
+    super::{escape, HostResult, Report},
+    crate::{errors::Result, structs::ScanStatus},
+    chrono::Utc,
+    std::{collections::BTreeMap, fmt::Write as _, io::Write},
+};
+
+/// Merge the Nmap XML files of every IP into a single Nmap XML document,
+/// adding every hostname that points to the IP as a `<hostname>` of its host.
+/// IPs without an XML file of this run (scanned without Nmap or removed with
+/// -k) are written from the unimap results, IPs that were not scanned or
+/// whose scan failed are left out
+pub fn write_nmap_xml<W: Write>(report: &Report, mut writer: W) -> Result<()> {
+    let mut by_ip: BTreeMap<&str, Vec<&HostResult>> = BTreeMap::new();
+    for host in &report.hosts {
+        if let Some(ip) = &host.ip {
+            by_ip.entry(ip).or_default().push(host);
+        }
+    }
+
+    let mut nmaprun = None;
+    let mut scaninfo = String::new();
+    let mut hosts = String::new();
+    let mut written = 0;
+    for (ip, results) in &by_ip {
+        let names: Vec<&str> = results.iter().map(|host| host.host.as_str()).collect();
+        let content = results
+            .iter()
+            .find_map(|host| host.nmap_xml.as_ref())
+            .and_then(|path| std::fs::read_to_string(path).ok());
+        match content
+            .as_deref()
+            .and_then(|content| host_element(content, ip))
+        {
+            Some(host) => {
+                let content = content.as_deref().unwrap_or_default();
+                if nmaprun.is_none() {
+                    nmaprun = element(content, "nmaprun").map(str::to_string);
+                    for info in elements(content, "scaninfo") {
+                        let _ = writeln!(scaninfo, "{info}");
+                    }
+                }
+                hosts.push_str(&add_hostnames(host, &names));
+            }
+            // All the hosts of the IP share the scan results
+            None if results[0].scan_status == ScanStatus::Ok.code() => {
+                hosts.push_str(&host_from_results(ip, results));
+            }
+            None => continue,
+        }
+        hosts.push('\n');
+        written += 1;
+    }
+
+    let now = Utc::now();
+    let startstr = now.format("%a %b %e %H:%M:%S %Y").to_string();
+    let nmaprun = nmaprun.unwrap_or_else(|| {
+        format!(
+            "<nmaprun scanner=\"nmap\" args=\"unimap {}\" start=\"{}\" startstr=\"{startstr}\" version=\"{}\" xmloutputversion=\"1.05\">",
+            escape(&report.unimap_version),
+            now.timestamp(),
+            escape(&report.unimap_version)
+        )
+    });
+    write!(
+        writer,
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE nmaprun>\n{nmaprun}\n{scaninfo}{hosts}<runstats><finished time=\"{}\" timestr=\"{startstr}\" summary=\"Merged by unimap {}\" exit=\"success\"/><hosts up=\"{}\" down=\"0\" total=\"{}\"/>\n</runstats>\n</nmaprun>\n",
+        now.timestamp(),
+        escape(&report.unimap_version),
+        written,
+        written
+    )?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Start of the first `<name>` or `<name ...>` tag, not matching longer
+/// names like `<hosthint>` for `<host>`
+fn tag_start(content: &str, name: &str, from: usize) -> Option<usize> {
+    let open = format!("<{name}");
+    let mut position = from;
+    while let Some(found) = content[position..].find(&open) {
+        let start = position + found;
+        match content[start + open.len()..].chars().next() {
+            Some('>' | '/') => return Some(start),
+            Some(c) if c.is_whitespace() => return Some(start),
+            _ => position = start + open.len(),
+        }
+    }
+    None
+}
+
+/// The opening tag of the first `name` element
+fn element<'a>(content: &'a str, name: &str) -> Option<&'a str> {
+    let start = tag_start(content, name, 0)?;
+    let end = start + content[start..].find('>')? + 1;
+    Some(&content[start..end])
+}
+
+/// Every empty `<name .../>` element
+fn elements<'a>(content: &'a str, name: &str) -> Vec<&'a str> {
+    let mut found = Vec::new();
+    let mut position = 0;
+    while let Some(start) = tag_start(content, name, position) {
+        match content[start..].find("/>") {
+            Some(end) => {
+                found.push(&content[start..start + end + 2]);
+                position = start + end + 2;
+            }
+            None => break,
+        }
+    }
+    found
+}
+
+/// The whole `<host>` element of `ip`, missing when Nmap didn't finish the scan
+fn host_element<'a>(content: &'a str, ip: &str) -> Option<&'a str> {
+    let address = format!("<address addr=\"{}\"", escape(ip));
+    let mut position = 0;
+    while let Some(start) = tag_start(content, "host", position) {
+        let end = start + content[start..].find("</host>")? + "</host>".len();
+        let host = &content[start..end];
+        if host.contains(&address) {
+            return Some(host);
+        }
+        position = end;
+    }
+    None
+}
+
+/// Add the hostnames that Nmap doesn't already list to a `<host>` element
+fn add_hostnames(host: &str, names: &[&str]) -> String {
+    let entries: String = names
+        .iter()
+        .map(|name| escape(name))
+        .filter(|name| !host.contains(&format!("<hostname name=\"{name}\"")))
+        .map(|name| format!("<hostname name=\"{name}\" type=\"user\"/>\n"))
+        .collect();
+    if entries.is_empty() {
+        return host.to_string();
+    }
+
+    let mut host = host.to_string();
+    if let Some(position) = host.find("</hostnames>") {
+        host.insert_str(position, &entries);
+    } else if let Some(position) = host.find("<hostnames/>") {
+        host.replace_range(
+            position..position + "<hostnames/>".len(),
+            &format!("<hostnames>\n{entries}</hostnames>"),
+        );
+    } else {
+        // Nmap writes <hostnames> right after the addresses
+        let position = tag_start(&host, "address", 0)
+            .and_then(|start| host[start..].find("/>").map(|end| start + end + 2))
+            .or_else(|| host.find("</host>"))
+            .unwrap_or(host.len());
+        host.insert_str(position, &format!("\n<hostnames>\n{entries}</hostnames>"));
+    }
+    host
+}
+
+/// `<host>` element built from the unimap results, for the IPs scanned
+/// without Nmap or whose XML file is gone
+fn host_from_results(ip: &str, results: &[&HostResult]) -> String {
+    let first = results[0];
+    let addrtype = if ip.contains(':') { "ipv6" } else { "ipv4" };
+    let mut host = format!(
+        "<host><status state=\"up\" reason=\"user-set\" reason_ttl=\"0\"/>\n<address addr=\"{}\" addrtype=\"{addrtype}\"/>\n<hostnames>\n",
+        escape(ip)
+    );
+    for result in results {
+        let _ = writeln!(
+            host,
+            "<hostname name=\"{}\" type=\"user\"/>",
+            escape(&result.host)
+        );
+    }
+    for ptr in &first.ptr {
+        let _ = writeln!(
+            host,
+            "<hostname name=\"{}\" type=\"PTR\"/>",
+            escape(ptr.trim_end_matches('.'))
+        );
+    }
+    host.push_str("</hostnames>\n<ports>");
+    for port in &first.ports {
+        let _ = write!(
+            host,
+            "<port protocol=\"{}\" portid=\"{}\"><state state=\"{}\" reason=\"{}\" reason_ttl=\"0\"/>",
+            escape(&port.protocol),
+            port.port,
+            escape(&port.state),
+            escape(&port.reason)
+        );
+        if let Some(service) = &port.service {
+            let _ = write!(host, "<service name=\"{}\"", escape(&service.name));
+            for (attribute, value) in [
+                ("product", &service.product),
+                ("version", &service.version),
+                ("extrainfo", &service.extrainfo),
+                ("ostype", &service.ostype),
+                ("tunnel", &service.tunnel),
+            ] {
+                if let Some(value) = value {
+                    let _ = write!(host, " {attribute}=\"{}\"", escape(value));
+                }
+            }
+            let _ = write!(
+                host,
+                " method=\"{}\" conf=\"{}\"/>",
+                escape(&service.method),
+                service.confidence
+            );
+        }
+        host.push_str("</port>\n");
+    }
+    host.push_str("</ports>\n");
+    if let Some(os) = &first.os {
+        let _ = writeln!(
+            host,
+            "<os><osmatch name=\"{}\" accuracy=\"{}\" line=\"0\"/></os>",
+            escape(&os.name),
+            os.accuracy
+        );
+    }
+    host.push_str("</host>");
+    host
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(name: &str, ip: &str, status: &ScanStatus, nmap_xml: Option<String>) -> HostResult {
+        HostResult {
+            host: name.to_string(),
+            ip: Some(ip.to_string()),
+            scan_status: status.code().to_string(),
+            nmap_xml,
+            ..HostResult::default()
+        }
+    }
+
+    #[test]
+    fn only_the_xml_files_of_the_run_are_merged() {
+        let path = std::env::temp_dir().join(format!("unimap-xml-{}.xml", std::process::id()));
+        std::fs::write(
+            &path,
+            "<nmaprun scanner=\"nmap\" args=\"nmap -sV\">\n<host><address addr=\"192.0.2.1\" addrtype=\"ipv4\"/><hostnames/><ports><port protocol=\"tcp\" portid=\"21\"/></ports></host>\n<host><address addr=\"192.0.2.2\" addrtype=\"ipv4\"/><hostnames/><ports><port protocol=\"tcp\" portid=\"22\"/></ports></host>\n</nmaprun>\n",
+        )
+        .unwrap();
+        let report = Report {
+            hosts: vec![
+                host(
+                    "nmap.test",
+                    "192.0.2.2",
+                    &ScanStatus::Ok,
+                    Some(path.display().to_string()),
+                ),
+                host("connect.test", "192.0.2.3", &ScanStatus::Ok, None),
+                host("failed.test", "192.0.2.4", &ScanStatus::Timeout, None),
+                host("skipped.test", "192.0.2.5", &ScanStatus::NotScanned, None),
+            ],
+            schema_version: 1,
+            unimap_version: String::from("0.7.0"),
+            generated_at: String::new(),
+        };
+
+        let mut xml = Vec::new();
+        write_nmap_xml(&report, &mut xml).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let xml = String::from_utf8(xml).unwrap();
+
+        assert!(xml.contains("<nmaprun scanner=\"nmap\" args=\"nmap -sV\">"));
+        assert!(xml.contains("<address addr=\"192.0.2.2\" addrtype=\"ipv4\"/><hostnames>\n<hostname name=\"nmap.test\" type=\"user\"/>\n</hostnames><ports><port protocol=\"tcp\" portid=\"22\"/>"));
+        assert!(!xml.contains("192.0.2.1"));
+        assert!(xml.contains("<status state=\"up\" reason=\"user-set\" reason_ttl=\"0\"/>\n<address addr=\"192.0.2.3\""));
+        assert!(!xml.contains("192.0.2.4"));
+        assert!(!xml.contains("192.0.2.5"));
+        assert!(xml.contains("<hosts up=\"2\" down=\"0\" total=\"2\"/>"));
+    }
+}
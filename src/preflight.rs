@@ -0,0 +1,214 @@
+use {
+    crate::{
+        args::{ProcessedArgs, ScannerBackend},
+        errors::{Result, ResultExt},
+        logic,
+    },
+    failure::bail,
+    log::{info, warn},
+    std::{
+        env,
+        path::{Path, PathBuf},
+        process::{Command, Stdio},
+    },
+};
+
+/// Oldest Nmap release that is known to produce the XML consumed by unimap
+const MIN_NMAP_VERSION: (u32, u32) = (7, 0);
+
+/// Locate a scanner binary. Paths containing a separator are used as given,
+/// bare names are looked up in the PATH environment variable
+pub fn locate_binary(binary_path: &str) -> Option<PathBuf> {
+    let candidate = Path::new(binary_path);
+    if candidate.components().count() > 1 {
+        return candidate.is_file().then(|| candidate.to_path_buf());
+    }
+
+    env::var_os("PATH").and_then(|paths| {
+        env::split_paths(&paths).find_map(|dir| {
+            let full_path = dir.join(binary_path);
+            if full_path.is_file() {
+                return Some(full_path);
+            }
+            #[cfg(windows)]
+            {
+                let full_path = full_path.with_extension("exe");
+                if full_path.is_file() {
+                    return Some(full_path);
+                }
+            }
+            None
+        })
+    })
+}
+
+/// Run `nmap --version` and return the version number, e.g. `7.94`
+pub fn nmap_version(nmap_path: &Path) -> Result<String> {
+    let output = Command::new(nmap_path)
+        .arg("--version")
+        .output()
+        .with_context(|_| format!("Can't execute {}", nmap_path.display()))?;
+
+    match String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Nmap version "))
+        .and_then(|line| line.split_whitespace().next())
+    {
+        Some(version) => Ok(version.to_string()),
+        None => bail!(
+            "{} --version did not report a Nmap version, is it really Nmap?",
+            nmap_path.display()
+        ),
+    }
+}
+
+fn parse_version(version: &str) -> (u32, u32) {
+    let mut parts = version
+        .split(|c: char| !c.is_ascii_digit())
+        .map(|part| part.parse().unwrap_or_default());
+    (
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or_default(),
+    )
+}
+
+/// Whether unimap runs as root/administrator, in which case the scanners it
+/// starts can open the raw sockets needed for SYN scans and OS detection
+#[cfg(unix)]
+pub fn is_root() -> bool {
+    if let Ok(status) = std::fs::read_to_string("/proc/self/status") {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix("Uid:"))
+            .and_then(|uids| uids.split_whitespace().nth(1))
+            == Some("0")
+    } else {
+        Command::new("id")
+            .arg("-u")
+            .output()
+            .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).trim() == "0")
+    }
+}
+
+/// On Windows we can't cheaply detect an elevated prompt, Nmap itself
+/// reports missing privileges when the scan starts
+#[cfg(not(unix))]
+pub fn is_root() -> bool {
+    true
+}
+
+/// Whether Nmap can open raw sockets without being root, e.g. because its
+/// binary has the `CAP_NET_RAW` file capability (`setcap`). The capabilities
+/// of unimap itself are not inherited by Nmap, so a quick SYN scan of the
+/// loopback interface is the reliable way to tell
+fn nmap_raw_sockets(nmap_path: &Path) -> bool {
+    Command::new(nmap_path)
+        .args([
+            "--privileged",
+            "-sS",
+            "-n",
+            "-Pn",
+            "-p",
+            "1",
+            "--max-retries",
+            "0",
+            "--host-timeout",
+            "5s",
+            "-oX",
+            "-",
+            "127.0.0.1",
+        ])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Verify that the selected scanners can be executed and adjust the scan
+/// options to the available privileges before spending time on DNS resolution
+pub fn check_scanners(args: &mut ProcessedArgs) -> Result<()> {
+    let root = is_root();
+
+    if args.scanner == ScannerBackend::Masscan {
+        args.masscan_path = match locate_binary(&args.masscan_path) {
+            Some(path) => path.display().to_string(),
+            None => bail!(
+                "masscan binary {} not found. Install masscan or use --masscan-path to point to it",
+                args.masscan_path
+            ),
+        };
+        if !root && !args.quiet_flag {
+            warn!("masscan sends raw packets, the scan will fail unless its binary has the CAP_NET_RAW capability (setcap cap_net_raw+ep).\n");
+        }
+    }
+
+    if args.scanner == ScannerBackend::Connect {
+        if !args.ports.is_empty() {
+            if let Err(e) = logic::parse_ports(&args.ports) {
+                bail!("Invalid --ports value: {}", e);
+            }
+        }
+        if !args.fast_scan && locate_binary(&args.nmap_path).is_none() {
+            args.fast_scan = true;
+            if !args.quiet_flag {
+                warn!(
+                    "Nmap binary {} not found, only the built-in connect scan will be done (no service detection).\n",
+                    args.nmap_path
+                );
+            }
+        }
+    }
+
+    if args.scanner.uses_nmap(args.fast_scan) {
+        check_nmap(args, root)?;
+    }
+    Ok(())
+}
+
+fn check_nmap(args: &mut ProcessedArgs, root: bool) -> Result<()> {
+    let nmap_path = match locate_binary(&args.nmap_path) {
+        Some(path) => path,
+        None => bail!(
+            "Nmap binary {} not found. Install Nmap or use --nmap-path to point to it",
+            args.nmap_path
+        ),
+    };
+    let version = nmap_version(&nmap_path)?;
+    if parse_version(&version) < MIN_NMAP_VERSION && !args.quiet_flag {
+        warn!(
+            "Nmap {version} is older than {}.{}, the scan results may not be parsed correctly.\n",
+            MIN_NMAP_VERSION.0, MIN_NMAP_VERSION.1
+        );
+    }
+    args.nmap_path = nmap_path.display().to_string();
+
+    // Nmap only trusts root by default, with file capabilities it needs to be told
+    if !root && nmap_raw_sockets(&nmap_path) {
+        args.nmap_privileged_flag = true;
+    } else if !root {
+        args.scan_type = String::from("-sT");
+        if !args.quiet_flag {
+            warn!("Running without root/administrator privileges and Nmap can't open raw sockets (no CAP_NET_RAW capability), falling back to Nmap TCP connect scan (-sT). It's slower and less stealthy than the default SYN scan.\n");
+        }
+        if args.os_detection {
+            args.os_detection = false;
+            if !args.quiet_flag {
+                warn!("OS detection requires raw sockets, disabling it.\n");
+            }
+        }
+    }
+
+    if !args.quiet_flag {
+        info!(
+            "Using Nmap {} from {} with {} scan.\n",
+            version,
+            args.nmap_path,
+            if args.scan_type == "-sS" {
+                "SYN"
+            } else {
+                "TCP connect"
+            }
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,278 @@
+use {
+    crate::errors::{Result, ResultExt},
+    failure::bail,
+    std::{
+        collections::{HashMap, HashSet},
+        net::Ipv4Addr,
+    },
+};
+
+/// Longest chain of CNAMEs followed to find the IPs of a host
+const MAX_CNAME_CHAIN: usize = 10;
+
+/// IPv4 addresses and CNAMEs of a host read from a resolution file
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Resolution {
+    pub ips: Vec<String>,
+    /// Targets of the CNAME records followed to get the IPs
+    pub cnames: Vec<String>,
+}
+
+/// A DNS record found in a resolution file
+enum Record {
+    /// A name that was resolved, even if it has no A records
+    Name(String),
+    /// Owner of a record of the massdns simple output, it was resolved
+    /// unless it's the target of a CNAME
+    Owner(String),
+    A(String, String),
+    Cname(String, String),
+}
+
+/// A line of the massdns ndjson output (`-o J`)
+#[derive(Deserialize)]
+struct MassdnsLine {
+    name: String,
+    #[serde(default)]
+    data: MassdnsData,
+}
+
+#[derive(Default, Deserialize)]
+struct MassdnsData {
+    #[serde(default)]
+    answers: Vec<MassdnsAnswer>,
+}
+
+#[derive(Deserialize)]
+struct MassdnsAnswer {
+    name: String,
+    #[serde(rename = "type")]
+    record_type: String,
+    data: String,
+}
+
+/// A line of the dnsx JSON output (`-json`), the CNAMEs are in chain order
+#[derive(Deserialize)]
+struct DnsxLine {
+    host: String,
+    #[serde(default)]
+    a: Vec<String>,
+    #[serde(default)]
+    cname: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonLine {
+    Massdns(MassdnsLine),
+    Dnsx(DnsxLine),
+}
+
+/// Read a resolution file and return the resolution of every name in it. The
+/// supported formats are `host,ip` lines, the massdns simple (`-o S`) and
+/// ndjson (`-o J`) outputs and the dnsx JSON output (`-json`)
+pub fn read_resolutions(path: &str) -> Result<HashMap<String, Resolution>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|_| format!("Can't read the resolutions file {path}"))?;
+
+    let mut names: HashSet<String> = HashSet::new();
+    let mut owners: HashSet<String> = HashSet::new();
+    let mut records: HashMap<String, (Vec<String>, Vec<String>)> = HashMap::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line_records = match parse_line(line) {
+            Ok(line_records) => line_records,
+            // Header of a CSV file
+            Err(_) if number == 0 && line.contains(',') => continue,
+            Err(e) => bail!("Line {} of {}: {}", number + 1, path, e),
+        };
+        for record in line_records {
+            match record {
+                Record::Name(name) => {
+                    names.insert(name);
+                }
+                Record::Owner(name) => {
+                    owners.insert(name);
+                }
+                Record::A(name, ip) => {
+                    let (ips, _) = records.entry(name).or_default();
+                    if !ips.contains(&ip) {
+                        ips.push(ip);
+                    }
+                }
+                Record::Cname(name, target) => {
+                    let (_, cnames) = records.entry(name).or_default();
+                    if !cnames.contains(&target) {
+                        cnames.push(target);
+                    }
+                }
+            }
+        }
+    }
+
+    let targets: HashSet<&String> = records.values().flat_map(|(_, cnames)| cnames).collect();
+    names.extend(owners.into_iter().filter(|owner| !targets.contains(owner)));
+
+    Ok(names
+        .into_iter()
+        .map(|name| {
+            let resolution = resolve(&name, &records);
+            (name, resolution)
+        })
+        .collect())
+}
+
+/// Parse a line of the file, records other than A and CNAME are ignored
+fn parse_line(line: &str) -> std::result::Result<Vec<Record>, String> {
+    if line.starts_with('{') {
+        return match serde_json::from_str(line).map_err(|e| e.to_string())? {
+            JsonLine::Massdns(massdns) => {
+                let mut records = vec![Record::Name(normalize(&massdns.name))];
+                for answer in massdns.data.answers {
+                    match answer.record_type.as_str() {
+                        "A" => records
+                            .push(Record::A(normalize(&answer.name), parse_ip(&answer.data)?)),
+                        "CNAME" => records.push(Record::Cname(
+                            normalize(&answer.name),
+                            normalize(&answer.data),
+                        )),
+                        _ => (),
+                    }
+                }
+                Ok(records)
+            }
+            JsonLine::Dnsx(dnsx) => {
+                // The A records belong to the end of the CNAME chain
+                let mut owner = normalize(&dnsx.host);
+                let mut records = vec![Record::Name(owner.clone())];
+                for cname in &dnsx.cname {
+                    let target = normalize(cname);
+                    records.push(Record::Cname(owner, target.clone()));
+                    owner = target;
+                }
+                for ip in &dnsx.a {
+                    records.push(Record::A(owner.clone(), parse_ip(ip)?));
+                }
+                Ok(records)
+            }
+        };
+    }
+
+    if let Some((host, ip)) = line.split_once(',') {
+        let host = normalize(host);
+        return Ok(vec![
+            Record::Name(host.clone()),
+            Record::A(host, parse_ip(ip.trim())?),
+        ]);
+    }
+    match line.split_whitespace().collect::<Vec<&str>>().as_slice() {
+        [name, record_type, data] => {
+            let name = normalize(name);
+            let record = match *record_type {
+                "A" => Some(Record::A(name.clone(), parse_ip(data)?)),
+                "CNAME" => Some(Record::Cname(name.clone(), normalize(data))),
+                _ => None,
+            };
+            Ok(std::iter::once(Record::Owner(name)).chain(record).collect())
+        }
+        _ => Err(String::from(
+            "expected host,ip, the massdns simple or ndjson output or the dnsx JSON output",
+        )),
+    }
+}
+
+fn parse_ip(ip: &str) -> std::result::Result<String, String> {
+    ip.parse::<Ipv4Addr>()
+        .map(|ip| ip.to_string())
+        .map_err(|_| format!("invalid IPv4 address {ip}"))
+}
+
+/// Lowercase name without the trailing dot of the DNS tools
+fn normalize(name: &str) -> String {
+    name.trim().trim_end_matches('.').to_lowercase()
+}
+
+/// IPs of `name`, following its CNAMEs when it has no A records
+fn resolve(name: &str, records: &HashMap<String, (Vec<String>, Vec<String>)>) -> Resolution {
+    let mut resolution = Resolution::default();
+    let mut current = name;
+    for _ in 0..MAX_CNAME_CHAIN {
+        match records.get(current) {
+            Some((ips, _)) if !ips.is_empty() => {
+                resolution.ips.clone_from(ips);
+                break;
+            }
+            Some((_, cnames)) if !cnames.is_empty() => {
+                current = &cnames[0];
+                resolution.cnames.push(current.to_string());
+            }
+            _ => break,
+        }
+    }
+    resolution
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(name: &str, content: &str) -> HashMap<String, Resolution> {
+        let path = std::env::temp_dir().join(format!("unimap-{name}-{}", std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        let resolutions = read_resolutions(&path.display().to_string()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        resolutions
+    }
+
+    #[test]
+    fn massdns_simple_cname_targets_are_not_names() {
+        let resolutions = read(
+            "massdns-simple",
+            "www.example.com. CNAME edge.example.net.\n\
+             edge.example.net. CNAME lb.example.org.\n\
+             lb.example.org. A 192.0.2.10\n\
+             lb.example.org. A 192.0.2.11\n\
+             api.example.com. A 192.0.2.20\n\
+             old.example.com. CNAME gone.example.net.\n",
+        );
+
+        let mut names: Vec<&String> = resolutions.keys().collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec!["api.example.com", "old.example.com", "www.example.com"]
+        );
+        assert_eq!(
+            resolutions["www.example.com"],
+            Resolution {
+                ips: vec![String::from("192.0.2.10"), String::from("192.0.2.11")],
+                cnames: vec![
+                    String::from("edge.example.net"),
+                    String::from("lb.example.org")
+                ],
+            }
+        );
+        assert_eq!(resolutions["api.example.com"].ips, vec!["192.0.2.20"]);
+        assert!(resolutions["old.example.com"].ips.is_empty());
+    }
+
+    #[test]
+    fn json_lines_keep_the_queried_names() {
+        let resolutions = read(
+            "massdns-json",
+            r#"{"name":"www.example.com.","type":"A","status":"NOERROR","data":{"answers":[{"ttl":300,"type":"CNAME","class":"IN","name":"www.example.com.","data":"edge.example.net."},{"ttl":60,"type":"A","class":"IN","name":"edge.example.net.","data":"192.0.2.10"}]}}
+{"host":"cdn.example.com","a":["192.0.2.30"],"cname":["cdn.example.net"]}
+"#,
+        );
+
+        assert_eq!(resolutions.len(), 2);
+        assert_eq!(resolutions["www.example.com"].ips, vec!["192.0.2.10"]);
+        assert_eq!(
+            resolutions["cdn.example.com"].cnames,
+            vec!["cdn.example.net"]
+        );
+    }
+}
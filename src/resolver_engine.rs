@@ -1,19 +1,28 @@
 use {
     crate::{
-        args::ProcessedArgs,
+        args::{CdnMode, CloudFilter, OutputFormat, ProcessedArgs},
+        asn::{AsnDatabase, AsnInfo},
+        cdn::CdnDetector,
+        cloud::{CloudInfo, CloudRanges},
+        defaults, diff,
         errors::Result,
-        files, logic, networking,
-        nmap::{self, Nmaprun},
-        structs::ResolvData,
+        files, fingerprint, http_probe, logic, misc, networking, output, preflight, scanner,
+        state::State,
+        structs::{ResolvData, ScanStatus},
+        tls,
     },
+    failure::bail,
     hickory_resolver::config::{LookupIpStrategy, ResolverOpts},
-    log::{error, info},
+    log::{error, info, warn},
     prettytable,
     prettytable::Table,
     rayon::prelude::*,
     std::{
         collections::{HashMap, HashSet},
+        io::Stdout,
         net::Ipv4Addr,
+        path::Path,
+        sync::atomic::{AtomicUsize, Ordering},
         time::Duration,
     },
 };
@@ -45,15 +54,58 @@ fn create_resolvers(args: &ProcessedArgs) -> Vec<Ipv4Addr> {
 }
 
 pub fn parallel_resolver_all(args: &mut ProcessedArgs) -> Result<()> {
-    if !files::check_full_path(&args.logs_dir) {
+    if let Some(dir) = &args.import_xml {
+        if !Path::new(dir).is_dir() {
+            bail!("The --import-xml directory {} was not found", dir)
+        }
+    } else if !files::check_full_path(&args.logs_dir) {
         error!("The logs directory {} does not exist.\n", args.logs_dir);
         std::process::exit(1)
     }
 
-    if !args.quiet_flag {
+    output::check_outputs(&args.outputs)?;
+    let baseline = match &args.baseline {
+        Some(path) => Some(diff::load(path, args.baseline_run)?),
+        None => None,
+    };
+    if args.import_xml.is_none() {
+        preflight::check_scanners(args)?;
+    }
+    let asn_db = match &args.asn_db {
+        Some(path) => Some(AsnDatabase::open(path)?),
+        None => None,
+    };
+    let cdn = match args.cdn {
+        Some(_) => Some(CdnDetector::new(args.cdn_ranges.as_deref())?),
+        None => None,
+    };
+    let cloud = if args.cloud_ranges.is_empty() {
+        None
+    } else {
+        Some(CloudRanges::load(&args.cloud_ranges)?)
+    };
+    // Nothing is scanned when importing, and the directory may be read-only
+    let state = match args.import_xml {
+        Some(_) => None,
+        None => Some(State::open(args)?),
+    };
+    misc::handle_interrupts();
+
+    // The hosts of the --resolutions file are not resolved again
+    let known = args
+        .targets
+        .iter()
+        .filter(|target| args.imported_resolutions.contains_key(*target))
+        .count();
+    if let Some(path) = &args.resolutions {
+        if !args.quiet_flag {
+            info!("Using the IPs of {known} targets from {path}.\n");
+        }
+    }
+    if !args.quiet_flag && args.import_xml.is_none() && known < args.targets.len() {
         info!(
             "Performing parallel resolution for {} targets with {} threads, it will take a while...\n",
-            args.targets.len(), args.threads
+            args.targets.len() - known, args.threads
         );
     }
 
@@ -64,70 +116,210 @@ pub fn parallel_resolver_all(args: &mut ProcessedArgs) -> Result<()> {
 
     let resolver = networking::get_resolver(networking::return_socket_address(args), opts);
 
-    let data = parallel_resolver_engine(args, &args.targets, &resolver);
+    let mut data = parallel_resolver_engine(
+        args,
+        &args.targets,
+        &resolver,
+        state.as_ref(),
+        cdn.as_ref(),
+        cloud.as_ref(),
+        &HashMap::new(),
+    )?;
+
+    if args.tls_certs {
+        let new_hosts = tls::new_hostnames(data.values(), &args.targets);
+        if !new_hosts.is_empty() && !args.quiet_flag {
+            let mut sorted: Vec<&String> = new_hosts.iter().collect();
+            sorted.sort();
+            info!(
+                "Found {} new hostnames in TLS certificates: {}\n",
+                new_hosts.len(),
+                sorted
+                    .iter()
+                    .map(|host| host.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            );
+        }
+        if args.resolve_new_hosts && !new_hosts.is_empty() && !misc::interrupted() {
+            if !args.quiet_flag {
+                info!(
+                    "Resolving and scanning {} new hostnames found in TLS certificates...\n",
+                    new_hosts.len()
+                );
+            }
+            // The IPs of the first round are not scanned again, only the new ones
+            let known: HashMap<String, ResolvData> = data
+                .values()
+                .filter(|resolv_data| !resolv_data.ip.is_empty())
+                .map(|resolv_data| {
+                    (
+                        resolv_data.ip.clone(),
+                        ResolvData {
+                            ips: Vec::new(),
+                            http_probes: Vec::new(),
+                            ..resolv_data.clone()
+                        },
+                    )
+                })
+                .collect();
+            match parallel_resolver_engine(
+                args,
+                &new_hosts,
+                &resolver,
+                state.as_ref(),
+                cdn.as_ref(),
+                cloud.as_ref(),
+                &known,
+            ) {
+                Ok(new_data) => {
+                    data.extend(new_data);
+                    args.targets.extend(new_hosts);
+                }
+                Err(e) => {
+                    if !args.quiet_flag {
+                        warn!("Second round for the new hostnames skipped: {e}\n");
+                    }
+                }
+            }
+        }
+    }
+
+    // Keep the state of an interrupted run so it can be resumed
+    if let Some(state) = state.filter(|_| !misc::interrupted()) {
+        state.remove();
+    }
+
+    if let Some(asn_db) = &asn_db {
+        for resolv_data in data.values_mut() {
+            resolv_data.asn = resolv_data.ip.parse().ok().and_then(|ip| asn_db.lookup(ip));
+        }
+    }
 
+    // Delete the args.logs_dir directory if it's empty
+    if args.no_keep_nmap_logs && std::fs::remove_dir(&args.logs_dir).is_err() {
+        error!("Error removing directory {}.", &args.logs_dir);
+    }
+
+    let report = output::Report::new(&data);
+    let mut saved_files = Vec::new();
+    for sink in &args.outputs {
+        if let Some(path) = &sink.path {
+            match files::output_to_file(args, &report, sink.format, path) {
+                Ok(()) => saved_files.push(path),
+                Err(e) => error!("An error occurred while writing the output file {path}: {e}\n"),
+            }
+        }
+    }
+    if let Some(baseline) = &baseline {
+        let json = matches!(
+            args.stdout_format(),
+            Some(OutputFormat::Json | OutputFormat::Jsonl | OutputFormat::JsonlPort)
+        );
+        diff::print_diff(&diff::compare(baseline, &report), json)?;
+    } else {
+        print_results(args, &data, &report)?;
+    }
+
+    let failed_scans: HashMap<&String, &ScanStatus> = data
+        .values()
+        .filter(|resolv_data| resolv_data.scan_status.is_failure())
+        .map(|resolv_data| (&resolv_data.ip, &resolv_data.scan_status))
+        .collect();
+    if !failed_scans.is_empty() && !args.quiet_flag {
+        error!(
+            "The scan of {} IPs did not complete successfully:",
+            failed_scans.len()
+        );
+        for (ip, status) in &failed_scans {
+            error!("{ip}: {status}");
+        }
+        println!();
+    }
+
+    if !saved_files.is_empty() && !args.quiet_flag {
+        info!(
+            "Job finished in {} seconds.\n",
+            args.time_wasted.elapsed().as_secs()
+        );
+        for path in saved_files {
+            info!("Output saved in {path}\n");
+        }
+        println!();
+    }
+    if !args.quiet_flag {
+        println!();
+    }
+    Ok(())
+}
+
+/// Print the results in the format chosen for stdout
+fn print_results(
+    args: &ProcessedArgs,
+    data: &HashMap<String, ResolvData>,
+    report: &output::Report,
+) -> Result<()> {
+    let stdout = std::io::stdout();
+    match args.stdout_format() {
+        Some(OutputFormat::Table) if !args.quiet_flag => results_table(args, data).printstd(),
+        Some(OutputFormat::Table) | None => (),
+        Some(OutputFormat::Csv) => output::csv::write_csv(
+            report,
+            &args.csv_columns,
+            args.csv_delimiter,
+            args.csv_header && !args.quiet_flag,
+            stdout.lock(),
+        )?,
+        Some(OutputFormat::Url) => {
+            if !args.quiet_flag {
+                println!("HOST:IP");
+            }
+            output::write_report(report, OutputFormat::Url, stdout.lock())?;
+        }
+        Some(format) => output::write_report(report, format, stdout.lock())?,
+    }
+
+    // The extra blocks go with the table and the CSV output
+    if matches!(
+        args.stdout_format(),
+        Some(OutputFormat::Table | OutputFormat::Csv)
+    ) && !args.quiet_flag
+    {
+        if args.http_probe {
+            print_http_probes(args, data)?;
+        }
+
+        if args.group_by_asn {
+            print_asn_summary(args, data)?;
+        }
+
+        if args.tls_certs {
+            print_tls_certificates(args, data)?;
+        }
+    }
+    Ok(())
+}
+
+/// The default output, one row per host with its services
+fn results_table(args: &ProcessedArgs, data: &HashMap<String, ResolvData>) -> Table {
     let mut table = Table::new();
     table.set_titles(row![
         bcFg => "HOST",
        "IP",
        "OPEN PORTS",
+       "OS",
+       "STATUS",
        "SERVICES"
     ]);
-    if args.raw_output && !args.quiet_flag {
-        println!("HOST,IP,PORT,SERVICE,VERSION,PRODUCT,OS,EXTRAINFO");
-    } else if args.url_output && !args.quiet_flag {
-        println!("HOST:IP");
-    }
-    for (target, resolv_data) in &data {
+    for (target, resolv_data) in data {
         if !resolv_data.ip.is_empty() {
-            if args.raw_output {
-                for port_data in &resolv_data.ports_data {
-                    println!(
-                        "{},{},{},{},{},{},{},{}",
-                        target,
-                        resolv_data.ip,
-                        port_data.portid,
-                        port_data.service.clone().unwrap_or_default().name,
-                        port_data
-                            .clone()
-                            .service
-                            .unwrap_or_default()
-                            .version
-                            .unwrap_or_else(|| "NULL".to_string()),
-                        port_data
-                            .clone()
-                            .service
-                            .clone()
-                            .unwrap_or_default()
-                            .product
-                            .unwrap_or_else(|| "NULL".to_string()),
-                        port_data
-                            .service
-                            .clone()
-                            .unwrap_or_default()
-                            .ostype
-                            .clone()
-                            .unwrap_or_else(|| "NULL".to_string()),
-                        port_data
-                            .service
-                            .clone()
-                            .unwrap_or_default()
-                            .extrainfo
-                            .clone()
-                            .unwrap_or_else(|| "NULL".to_string())
-                    );
-                }
-            } else if args.url_output {
-                for port_data in &resolv_data.ports_data {
-                    println!("{}:{}", target, port_data.portid);
-                }
-            } else {
+            {
                 let mut services_table = Table::new();
                 for port_data in &resolv_data.ports_data {
                     services_table
                         .add_row(row![bc => &format!("PORT => {}", port_data.portid.clone())]);
                     services_table.add_row(
-                    row![c => &format!("SERVICE: {}", port_data.service.clone().unwrap_or_default().name)],
+                    row![c => &format!("SERVICE: {}", logic::return_service_name(&port_data.service))],
                 );
                     services_table.add_row(row![c => &format!("VERSION: {}" ,port_data
                 .service.clone().unwrap_or_default()
@@ -152,7 +344,7 @@ pub fn parallel_resolver_all(args: &mut ProcessedArgs) -> Result<()> {
                 }
                 table.add_row(row![ d =>
                     target,
-                    logic::null_ip_checker(&resolv_data.ip),
+                    ip_details(args, resolv_data),
                     logic::return_ports_string(
                         &resolv_data
                             .ports_data
@@ -160,54 +352,299 @@ pub fn parallel_resolver_all(args: &mut ProcessedArgs) -> Result<()> {
                             .map(|f| f.portid.clone())
                             .collect(),
                     ),
+                    logic::return_os_string(&resolv_data.os),
+                    resolv_data.scan_status,
                     services_table,
                 ]);
             }
         }
     }
 
-    if args.with_output
-        && !args.targets.is_empty()
-        && files::table_to_file(&table, files::return_output_file(args)).is_err()
-        && !args.quiet_flag
-    {
-        error!(
-            "An error occurred while writing the output file {}.\n",
-            args.file_name
+    table
+}
+
+/// IP cell of the table with the optional PTR and ASN data below it
+fn ip_details(args: &ProcessedArgs, resolv_data: &ResolvData) -> String {
+    let mut details = vec![logic::null_ip_checker(&resolv_data.ip)];
+    if args.reverse_dns {
+        details.push(logic::return_ptr_string(&resolv_data.ptr, "\n"));
+    }
+    if args.asn_db.is_some() {
+        details.push(logic::return_asn_string(&resolv_data.asn));
+    }
+    if args.cdn.is_some() {
+        details.push(format!(
+            "CDN: {}",
+            resolv_data.cdn.as_deref().unwrap_or("NULL")
+        ));
+    }
+    if !args.cloud_ranges.is_empty() {
+        details.push(format!(
+            "CLOUD: {}",
+            logic::return_cloud_string(&resolv_data.cloud)
+        ));
+    }
+    details.join("\n")
+}
+
+/// CSV writer on stdout for the extra blocks of the raw output, a blank line
+/// separates them from the previous block
+fn raw_writer(args: &ProcessedArgs, header: &[&str]) -> Result<Option<csv::Writer<Stdout>>> {
+    if args.stdout_format() != Some(OutputFormat::Csv) {
+        return Ok(None);
+    }
+    println!();
+    let mut writer = output::csv::writer(args.csv_delimiter, std::io::stdout());
+    if args.csv_header {
+        writer.write_record(header)?;
+    }
+    Ok(Some(writer))
+}
+
+fn print_asn_summary(args: &ProcessedArgs, data: &HashMap<String, ResolvData>) -> Result<()> {
+    let mut groups: HashMap<Option<&AsnInfo>, (HashSet<&String>, HashSet<&String>)> =
+        HashMap::new();
+    for (target, resolv_data) in data {
+        if !resolv_data.ip.is_empty() {
+            let (ips, hosts) = groups.entry(resolv_data.asn.as_ref()).or_default();
+            ips.insert(&resolv_data.ip);
+            hosts.insert(target);
+        }
+    }
+    let mut groups: Vec<_> = groups.into_iter().collect();
+    groups.sort_by(|(a_asn, (_, a_hosts)), (b_asn, (_, b_hosts))| {
+        b_hosts.len().cmp(&a_hosts.len()).then_with(|| {
+            a_asn
+                .map(|asn| asn.number)
+                .cmp(&b_asn.map(|asn| asn.number))
+        })
+    });
+
+    let mut table = Table::new();
+    table.set_titles(row![
+        bcFg => "ASN",
+       "ORGANIZATION",
+       "COUNTRY",
+       "IPS",
+       "HOSTS"
+    ]);
+    let mut raw = raw_writer(args, &["ASN", "AS_ORG", "COUNTRY", "IPS", "HOSTS"])?;
+    for (asn, (ips, hosts)) in groups {
+        let (name, org, country) = asn.map_or_else(
+            || ("NULL".to_string(), "NULL".to_string(), "NULL".to_string()),
+            |asn| {
+                (
+                    asn.name(),
+                    asn.org.clone(),
+                    asn.country.clone().unwrap_or_else(|| "NULL".to_string()),
+                )
+            },
         );
+        if let Some(raw) = &mut raw {
+            raw.write_record([
+                name,
+                org,
+                country,
+                ips.len().to_string(),
+                hosts.len().to_string(),
+            ])?;
+        } else {
+            table.add_row(row![name, org, country, ips.len(), hosts.len()]);
+        }
     }
-    if !args.quiet_flag && !args.raw_output && !args.url_output {
+    if let Some(raw) = &mut raw {
+        raw.flush()?;
+    } else if !table.is_empty() {
         table.printstd();
     }
+    Ok(())
+}
 
-    if (args.with_output || args.unique_output_flag) && !args.quiet_flag {
-        info!(
-            "Job finished in {} seconds.\n",
-            args.time_wasted.elapsed().as_secs()
-        );
-        info!("Logfile saved in {}\n\n", args.file_name);
+fn print_http_probes(args: &ProcessedArgs, data: &HashMap<String, ResolvData>) -> Result<()> {
+    let mut table = Table::new();
+    table.set_titles(row![
+        bcFg => "HOST",
+       "URL",
+       "STATUS",
+       "TITLE",
+       "SERVER",
+       "LOCATION",
+       "LENGTH"
+    ]);
+    let mut raw = raw_writer(
+        args,
+        &[
+            "HOST", "URL", "STATUS", "TITLE", "SERVER", "LOCATION", "LENGTH",
+        ],
+    )?;
+    for (target, resolv_data) in data {
+        for probe in &resolv_data.http_probes {
+            let status = probe.status.map_or_else(
+                || probe.error.clone().unwrap_or_else(|| "NULL".to_string()),
+                |status| status.to_string(),
+            );
+            let title = probe.title.clone().unwrap_or_else(|| "NULL".to_string());
+            let server = probe.server.clone().unwrap_or_else(|| "NULL".to_string());
+            let location = probe.location.clone().unwrap_or_else(|| "NULL".to_string());
+            let length = probe
+                .content_length
+                .map_or_else(|| "NULL".to_string(), |length| length.to_string());
+            if let Some(raw) = &mut raw {
+                raw.write_record([
+                    target, &probe.url, &status, &title, &server, &location, &length,
+                ])?;
+            } else {
+                table.add_row(row![
+                    target, probe.url, status, title, server, location, length
+                ]);
+            }
+        }
     }
-    if !args.quiet_flag {
-        println!();
+    if let Some(raw) = &mut raw {
+        raw.flush()?;
+    } else if !table.is_empty() {
+        table.printstd();
     }
     Ok(())
 }
 
+fn print_tls_certificates(args: &ProcessedArgs, data: &HashMap<String, ResolvData>) -> Result<()> {
+    let mut table = Table::new();
+    table.set_titles(row![
+        bcFg => "IP",
+       "PORT",
+       "SUBJECT",
+       "SANS",
+       "ISSUER",
+       "NOT BEFORE",
+       "NOT AFTER",
+       "SHA256"
+    ]);
+    let mut raw = raw_writer(
+        args,
+        &[
+            "IP",
+            "PORT",
+            "SUBJECT",
+            "SANS",
+            "ISSUER",
+            "NOT_BEFORE",
+            "NOT_AFTER",
+            "SHA256",
+        ],
+    )?;
+    // Certificates belong to the IP, print them once no matter how many hosts point to it
+    let mut by_ip: Vec<&ResolvData> = data
+        .values()
+        .filter(|resolv_data| !resolv_data.tls.is_empty())
+        .collect();
+    by_ip.sort_by(|a, b| a.ip.cmp(&b.ip));
+    by_ip.dedup_by(|a, b| a.ip == b.ip);
+    for resolv_data in by_ip {
+        for tls_info in &resolv_data.tls {
+            if let Some(leaf) = tls_info.leaf() {
+                if let Some(raw) = &mut raw {
+                    raw.write_record([
+                        &resolv_data.ip,
+                        &tls_info.port,
+                        &leaf.subject,
+                        &leaf.sans.join(" "),
+                        &leaf.issuer,
+                        &leaf.not_before,
+                        &leaf.not_after,
+                        &leaf.fingerprint_sha256,
+                    ])?;
+                } else {
+                    table.add_row(row![
+                        resolv_data.ip,
+                        tls_info.port,
+                        leaf.subject,
+                        leaf.sans.join("\n"),
+                        leaf.issuer,
+                        leaf.not_before,
+                        leaf.not_after,
+                        leaf.fingerprint_sha256
+                    ]);
+                }
+            }
+        }
+    }
+    if let Some(raw) = &mut raw {
+        raw.flush()?;
+    } else if !table.is_empty() {
+        table.printstd();
+    }
+    Ok(())
+}
+
+/// Resolve and scan the targets, the IPs in `known` already have their
+/// results (scan, banners, certificates and PTR records) and are not scanned again
 fn parallel_resolver_engine(
     args: &ProcessedArgs,
     targets: &HashSet<String>,
     resolver: &hickory_resolver::TokioResolver,
-) -> HashMap<String, ResolvData> {
-    let resolv_data: HashMap<String, ResolvData> = targets
+    state: Option<&State>,
+    cdn: Option<&CdnDetector>,
+    cloud: Option<&CloudRanges>,
+    known: &HashMap<String, ResolvData>,
+) -> Result<HashMap<String, ResolvData>> {
+    let mut resolv_data: HashMap<String, ResolvData> = targets
         .par_iter()
         .map(|target| {
+            if let Some(resolv_data) = state.and_then(|state| state.resolved(target)) {
+                return (target.to_owned(), resolv_data.clone());
+            }
             let fqdn_target = format!("{target}.");
             let mut resolv_data = ResolvData::default();
-            resolv_data.ip = networking::get_records(resolver, &fqdn_target);
+            if let Some(resolution) = args.imported_resolutions.get(target) {
+                resolv_data.ips.clone_from(&resolution.ips);
+                resolv_data.ip = resolv_data.ips.first().cloned().unwrap_or_default();
+                if let Some(cdn) = cdn.filter(|_| !resolv_data.ip.is_empty()) {
+                    resolv_data.cdn = cdn.by_cnames(&resolution.cnames);
+                }
+            } else if args.import_xml.is_none() && !misc::interrupted() {
+                let (ips, cnames) = networking::get_records(resolver, &fqdn_target);
+                resolv_data.ips = ips;
+                resolv_data.ip = resolv_data.ips.first().cloned().unwrap_or_default();
+                if let Some(cdn) = cdn.filter(|_| !resolv_data.ip.is_empty()) {
+                    resolv_data.cdn = cdn.by_cnames(&cnames);
+                }
+                if let Some(state) = state {
+                    state.save_resolution(target, &resolv_data);
+                }
+            }
             (target.to_owned(), resolv_data)
         })
         .collect();
 
+    // An IP is an edge IP when it's in a CDN range or any of its hostnames
+    // is a CNAME to a CDN
+    let mut cdn_ips: HashMap<String, String> = HashMap::new();
+    if let Some(cdn) = cdn {
+        for resolv_data in resolv_data.values() {
+            if let Some(provider) = resolv_data
+                .cdn
+                .clone()
+                .or_else(|| cdn.by_ip(&resolv_data.ip))
+            {
+                cdn_ips.entry(resolv_data.ip.clone()).or_insert(provider);
+            }
+        }
+        for resolv_data in resolv_data.values_mut() {
+            resolv_data.cdn = cdn_ips.get(&resolv_data.ip).cloned();
+        }
+    }
+
+    let mut cloud_ips: HashMap<String, CloudInfo> = HashMap::new();
+    if let Some(cloud) = cloud {
+        for resolv_data in resolv_data.values_mut() {
+            resolv_data.cloud = cloud.lookup(&resolv_data.ip);
+            if let Some(cloud_info) = &resolv_data.cloud {
+                cloud_ips.insert(resolv_data.ip.clone(), cloud_info.clone());
+            }
+        }
+    }
+
     let resolvers = create_resolvers(args);
 
     let mut nmap_ips: HashSet<String> = resolv_data
@@ -224,72 +661,186 @@ fn parallel_resolver_engine(
     });
 
     if nmap_ips.is_empty() {
-        error!("No valid IPs found for scanning. IPs found: {nmap_ips_orig:?}\n");
-        std::process::exit(1)
+        bail!(
+            "No valid IPs found for scanning. IPs found: {:?}",
+            nmap_ips_orig
+        )
     } else {
-        let nmap_data: HashMap<String, Nmaprun> = nmap_ips
-            .par_iter()
-            .map(|ip| {
-                let filename = format!("{}/{}.xml", &args.logs_dir, &ip);
-                match nmap::get_nmap_data(
-                    &filename,
-                    ip,
-                    &args.min_rate,
-                    &args.ports,
-                    args.fast_scan,
-                    &resolvers,
-                ) {
-                    Ok(nmap_data) => {
-                        nmap_data
-                            .host
-                            .clone()
-                            .unwrap_or_default()
-                            .ports
-                            .unwrap_or_default()
-                            .port
-                            .retain(|f| f.state.state == "open");
-                        if args.no_keep_nmap_logs && std::fs::remove_file(&filename).is_err() {
-                            error!("Error removing filename {}.", &filename);
-                        }
-                        (ip.clone(), nmap_data)
-                    }
-                    Err(e) => {
-                        error!("Error scanning the ip {}. Description: {}", &ip, e);
-                        (String::new(), Nmaprun::default())
+        nmap_ips.retain(|ip| !known.contains_key(ip));
+        if let Some(filter) = args.cloud_filter {
+            let total = nmap_ips.len();
+            nmap_ips.retain(|ip| cloud_ips.contains_key(ip) == (filter == CloudFilter::Only));
+            if !args.quiet_flag {
+                info!(
+                    "Skipping {} IPs that {} a cloud provider.\n",
+                    total - nmap_ips.len(),
+                    if filter == CloudFilter::Only {
+                        "don't belong to"
+                    } else {
+                        "belong to"
                     }
+                );
+            }
+        }
+
+        let edge_ips = nmap_ips
+            .iter()
+            .filter(|ip| cdn_ips.contains_key(*ip))
+            .count();
+        if edge_ips > 0 && !args.quiet_flag {
+            match args.cdn {
+                Some(CdnMode::Skip) => info!("Skipping {edge_ips} IPs that belong to a CDN.\n"),
+                Some(CdnMode::WebPorts) if args.import_xml.is_none() => info!(
+                    "Scanning only the ports {} of {edge_ips} IPs that belong to a CDN.\n",
+                    defaults::CDN_WEB_PORTS
+                ),
+                _ => (),
+            }
+        }
+
+        let cdn_args = ProcessedArgs {
+            ports: defaults::CDN_WEB_PORTS.to_string(),
+            custom_ports_range: true,
+            ..args.clone()
+        };
+        let cdn_scanner =
+            if args.cdn == Some(CdnMode::WebPorts) && edge_ips > 0 && args.import_xml.is_none() {
+                Some(scanner::new_scanner(&cdn_args, resolvers.clone())?)
+            } else {
+                None
+            };
+        let scanner = scanner::new_scanner(args, resolvers)?;
+        let (cdn_pending, pending): (Vec<&str>, Vec<&str>) = nmap_ips
+            .iter()
+            .filter(|ip| {
+                state.and_then(|state| state.scanned(ip)).is_none()
+                    && !(cdn_ips.contains_key(*ip) && args.cdn == Some(CdnMode::Skip))
+            })
+            .map(String::as_str)
+            .partition(|ip| cdn_ips.contains_key(*ip) && cdn_scanner.is_some());
+        scanner.prepare(args, &pending);
+        if let Some(cdn_scanner) = &cdn_scanner {
+            cdn_scanner.prepare(&cdn_args, &cdn_pending);
+        }
+        let scanned = AtomicUsize::new(0);
+        let mut scan_data: HashMap<String, ResolvData> = nmap_ips
+            .par_iter()
+            .filter_map(|ip| {
+                if let Some(scan_data) = state.and_then(|state| state.scanned(ip)) {
+                    return Some((ip.clone(), scan_data.clone()));
+                }
+                if cdn_ips.contains_key(ip) && args.cdn == Some(CdnMode::Skip) {
+                    return None;
                 }
+                scanned.fetch_add(1, Ordering::Relaxed);
+                let mut scan_data = match (cdn_ips.get(ip), &cdn_scanner) {
+                    (Some(_), Some(cdn_scanner)) => cdn_scanner.scan(&cdn_args, ip),
+                    _ => scanner.scan(args, ip),
+                };
+                scan_data.cdn = cdn_ips.get(ip).cloned();
+                scan_data.cloud = cloud_ips.get(ip).cloned();
+                if let Some(state) = state {
+                    state.save_scan(ip, &scan_data);
+                }
+                Some((ip.clone(), scan_data))
             })
             .collect();
 
-        // Delete the args.logs_dir directory if it's empty
-        if args.no_keep_nmap_logs && std::fs::remove_dir(&args.logs_dir).is_err() {
-            error!("Error removing directory {}.", &args.logs_dir);
+        if args.scan_cache.is_some() && !args.quiet_flag {
+            let cache_hits = scanner.cache_hits()
+                + cdn_scanner
+                    .as_ref()
+                    .map_or(0, |cdn_scanner| cdn_scanner.cache_hits());
+            info!(
+                "Scan cache: {cache_hits} IPs reused from {}, {} IPs scanned.\n",
+                args.logs_dir,
+                scanned.into_inner() - cache_hits
+            );
+        }
+
+        if args.banners {
+            if !args.quiet_flag && !scan_data.is_empty() {
+                info!("Grabbing banners of the services not identified by Nmap...\n");
+            }
+            scan_data
+                .par_iter_mut()
+                .for_each(|(_, scan_data)| fingerprint::fingerprint_services(scan_data));
+        }
+
+        if args.tls_certs {
+            if !args.quiet_flag && !scan_data.is_empty() {
+                info!("Collecting TLS certificates of the open ports...\n");
+            }
+            // Send one of the hostnames of the IP as SNI, servers with many
+            // certificates often don't answer a handshake without it
+            let mut server_names: HashMap<&str, &str> = HashMap::new();
+            for (target, resolv_data) in &resolv_data {
+                let name = server_names
+                    .entry(resolv_data.ip.as_str())
+                    .or_insert(target.as_str());
+                if target.as_str() < *name {
+                    *name = target.as_str();
+                }
+            }
+            scan_data.par_iter_mut().for_each(|(ip, scan_data)| {
+                tls::collect_certificates(scan_data, server_names.get(ip.as_str()).copied());
+            });
         }
 
-        resolv_data
+        let mut data: HashMap<String, ResolvData> = resolv_data
             .iter()
             .map(|(target, resolv_data)| {
                 (
                     target.clone(),
-                    ResolvData {
-                        ip: resolv_data.ip.clone(),
-                        ports_data: if resolv_data.ip.is_empty() {
-                            resolv_data.ports_data.clone()
-                        } else {
-                            nmap_data
-                                .get_key_value(&resolv_data.ip)
-                                .unwrap()
-                                .1
-                                .host
-                                .clone()
-                                .unwrap_or_default()
-                                .ports
-                                .unwrap_or_default()
-                                .port
-                        },
-                    },
+                    scan_data
+                        .get(&resolv_data.ip)
+                        .or_else(|| known.get(&resolv_data.ip))
+                        .map_or_else(
+                            || resolv_data.clone(),
+                            |scan_data| ResolvData {
+                                ips: resolv_data.ips.clone(),
+                                ..scan_data.clone()
+                            },
+                        ),
                 )
             })
-            .collect()
+            .collect();
+
+        if args.reverse_dns {
+            let ips: HashSet<&String> = data
+                .values()
+                .map(|resolv_data| &resolv_data.ip)
+                .filter(|ip| !ip.is_empty() && !known.contains_key(*ip))
+                .collect();
+            if !args.quiet_flag && !ips.is_empty() {
+                info!("Looking up the PTR records of {} IPs...\n", ips.len());
+            }
+            let ptr_records: HashMap<String, Vec<String>> = ips
+                .par_iter()
+                .filter(|_| !misc::interrupted())
+                .filter_map(|ip| {
+                    ip.parse()
+                        .ok()
+                        .map(|addr| ((*ip).clone(), networking::get_ptr_records(resolver, addr)))
+                })
+                .collect();
+            for resolv_data in data.values_mut() {
+                if let Some(names) = ptr_records.get(&resolv_data.ip) {
+                    resolv_data.ptr.clone_from(names);
+                }
+            }
+        }
+
+        if args.http_probe {
+            if !args.quiet_flag {
+                info!("Probing the web ports of every hostname...\n");
+            }
+            data.par_iter_mut().for_each(|(target, resolv_data)| {
+                resolv_data.http_probes =
+                    http_probe::probe_host(target, &resolv_data.ip, &resolv_data.ports_data);
+            });
+        }
+
+        Ok(data)
     }
 }
@@ -0,0 +1,765 @@
+use {
+    crate::{
+        args::{ProcessedArgs, ScannerBackend},
+        defaults,
+        errors::{self, ResultExt},
+        logic, misc, networking,
+        nmap::{self, NmapHosts, Port, State},
+        structs::{ResolvData, ScanStatus},
+    },
+    failure::err_msg,
+    futures::stream::{self, StreamExt},
+    log::{error, info, warn},
+    std::{
+        collections::HashMap,
+        io::Read,
+        net::Ipv4Addr,
+        path::Path,
+        process::{Command, Output, Stdio},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Mutex, OnceLock, PoisonError,
+        },
+        thread::{self, JoinHandle},
+        time::{Duration, Instant},
+    },
+    tokio::net::TcpStream,
+};
+
+const WAIT_INTERVAL: Duration = Duration::from_millis(200);
+/// Extra time given to a scanner to honour --host-timeout before killing it
+const HOST_TIMEOUT_GRACE: Duration = Duration::from_secs(60);
+
+const ERROR_LINES: usize = 3;
+const ERROR_MAX_LEN: usize = 300;
+
+/// Ports scanned by masscan when --ports is not used, like Nmap does by default
+const MASSCAN_TOP_PORTS: &str = "1000";
+
+/// Simultaneous connection attempts per IP of the connect scanner
+const CONNECT_CONCURRENCY: usize = 256;
+/// Time to wait for a TCP handshake before considering the port filtered
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// A port scanner that finds the open ports of a single IP
+pub trait PortScanner: Sync {
+    /// Called once with all the IPs to scan before any `scan`, for scanners
+    /// that are faster when they get the whole list at once
+    fn prepare(&self, _args: &ProcessedArgs, _ips: &[&str]) {}
+
+    /// Scan the IP, failures are reported in the `scan_status` of the result
+    fn scan(&self, args: &ProcessedArgs, ip: &str) -> ResolvData;
+
+    /// Number of IPs whose Nmap results were reused from --scan-cache
+    fn cache_hits(&self) -> usize;
+}
+
+pub fn new_scanner(
+    args: &ProcessedArgs,
+    resolvers: Vec<Ipv4Addr>,
+) -> errors::Result<Box<dyn PortScanner>> {
+    if let Some(dir) = &args.import_xml {
+        return Ok(Box::new(ImportScanner::load(args, dir)?));
+    }
+    Ok(match args.scanner {
+        ScannerBackend::Nmap => Box::new(NmapScanner::new(resolvers)),
+        ScannerBackend::Masscan => Box::new(MasscanScanner {
+            nmap: NmapScanner::new(resolvers),
+            discovered: OnceLock::new(),
+        }),
+        ScannerBackend::Connect => Box::new(ConnectScanner {
+            ports: logic::parse_ports(if args.ports.is_empty() {
+                defaults::TOP_PORTS
+            } else {
+                &args.ports
+            })
+            .map_err(err_msg)?,
+            limiter: RateLimiter::new(args.rate),
+            nmap: (!args.fast_scan).then(|| NmapScanner::new(resolvers)),
+        }),
+    })
+}
+
+/// The default backend, everything is done by Nmap
+pub struct NmapScanner {
+    resolvers: Vec<Ipv4Addr>,
+    cache_hits: AtomicUsize,
+}
+
+impl NmapScanner {
+    const fn new(resolvers: Vec<Ipv4Addr>) -> Self {
+        Self {
+            resolvers,
+            cache_hits: AtomicUsize::new(0),
+        }
+    }
+
+    fn scan_ports(&self, args: &ProcessedArgs, ip: &str, ports: &str) -> ResolvData {
+        let filename = format!("{}/{}.xml", &args.logs_dir, &ip);
+        let mut scan_data = ResolvData {
+            ip: ip.to_string(),
+            ..ResolvData::default()
+        };
+        let cached = args
+            .scan_cache
+            .and_then(|max_age| nmap::cached_nmap_data(args, &filename, ip, ports, max_age));
+        if cached.is_some() {
+            self.cache_hits.fetch_add(1, Ordering::Relaxed);
+        }
+        match cached.map_or_else(
+            || nmap::get_nmap_data(args, &filename, ip, ports, &self.resolvers),
+            Ok,
+        ) {
+            Ok(nmap_data) => {
+                scan_data.nmap_xml = (!args.no_keep_nmap_logs).then(|| filename.clone());
+                scan_data.ports_data = nmap_data.open_ports();
+                scan_data.os = nmap_data.best_os_match();
+                scan_data.scan_status = if nmap_data.timed_out() {
+                    ScanStatus::Timeout
+                } else {
+                    ScanStatus::Ok
+                };
+            }
+            Err(status) => scan_data.scan_status = status,
+        }
+        if args.no_keep_nmap_logs
+            && Path::new(&filename).exists()
+            && std::fs::remove_file(&filename).is_err()
+        {
+            error!("Error removing filename {}.", &filename);
+        }
+        scan_data
+    }
+}
+
+impl PortScanner for NmapScanner {
+    fn scan(&self, args: &ProcessedArgs, ip: &str) -> ResolvData {
+        self.scan_ports(args, ip, &args.ports)
+    }
+
+    fn cache_hits(&self) -> usize {
+        self.cache_hits.load(Ordering::Relaxed)
+    }
+}
+
+/// Two-phase backend: masscan discovers the open ports of all the IPs in a
+/// single run and Nmap only looks at those for service detection
+pub struct MasscanScanner {
+    nmap: NmapScanner,
+    /// Open ports of every IP found by the masscan run of `prepare`
+    discovered: OnceLock<Result<HashMap<String, Vec<Port>>, ScanStatus>>,
+}
+
+impl MasscanScanner {
+    /// Run masscan over `ips`, `name` is the base name of its files in the logs directory
+    fn discover(
+        args: &ProcessedArgs,
+        ips: &[&str],
+        name: &str,
+    ) -> Result<HashMap<String, Vec<Port>>, ScanStatus> {
+        if ips.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let targets_file = format!("{}/{name}.targets", &args.logs_dir);
+        let filename = format!("{}/{name}.masscan", &args.logs_dir);
+        std::fs::write(&targets_file, format!("{}\n", ips.join("\n"))).map_err(|e| {
+            ScanStatus::DiscoveryError(format!(
+                "can't write the masscan targets {targets_file}: {e}"
+            ))
+        })?;
+
+        let rate = args.rate.to_string();
+        let ports = args.ports.replace(' ', "");
+        let mut masscan_args = vec![
+            "-iL",
+            &targets_file,
+            "--rate",
+            &rate,
+            "--wait",
+            "3",
+            "-oL",
+            &filename,
+        ];
+        if ports.is_empty() {
+            masscan_args.append(&mut vec!["--top-ports", MASSCAN_TOP_PORTS]);
+        } else {
+            masscan_args.append(&mut vec!["-p", &ports]);
+        }
+
+        // --host-timeout is per IP, masscan always stops once all the packets
+        // are sent and the --wait is over
+        let output = run_command(
+            &args.masscan_path,
+            &masscan_args,
+            None,
+            ScanStatus::DiscoveryError,
+        );
+        let list = output.and_then(|output| {
+            std::fs::read_to_string(&filename).map_err(|e| {
+                ScanStatus::DiscoveryError(format!(
+                    "masscan exited with {} but {filename} can't be read: {e}",
+                    output.status
+                ))
+            })
+        });
+        for file in [&targets_file, &filename] {
+            if std::fs::remove_file(file).is_err() && Path::new(file).exists() {
+                error!("Error removing filename {}.", file);
+            }
+        }
+        Ok(parse_masscan_list(&list?))
+    }
+}
+
+impl PortScanner for MasscanScanner {
+    fn prepare(&self, args: &ProcessedArgs, ips: &[&str]) {
+        if !ips.is_empty() && !args.quiet_flag {
+            info!("Running masscan on {} IPs...\n", ips.len());
+        }
+        let _ = self
+            .discovered
+            .set(Self::discover(args, ips, "unimap-masscan"));
+    }
+
+    fn scan(&self, args: &ProcessedArgs, ip: &str) -> ResolvData {
+        // Without `prepare` masscan is run for this IP alone
+        let discovered = match self.discovered.get() {
+            Some(Ok(discovered)) => Ok(discovered.get(ip).cloned().unwrap_or_default()),
+            Some(Err(status)) => Err(status.clone()),
+            None => Self::discover(args, &[ip], ip)
+                .map(|mut discovered| discovered.remove(ip).unwrap_or_default()),
+        };
+        let ports_data = match discovered {
+            Ok(ports_data) => ports_data,
+            Err(status) => {
+                return ResolvData {
+                    ip: ip.to_string(),
+                    scan_status: status,
+                    ..ResolvData::default()
+                }
+            }
+        };
+
+        if args.fast_scan || ports_data.is_empty() {
+            ResolvData {
+                ip: ip.to_string(),
+                ports_data,
+                scan_status: ScanStatus::Ok,
+                ..ResolvData::default()
+            }
+        } else {
+            let ports = ports_data
+                .iter()
+                .map(|port| port.portid.as_str())
+                .collect::<Vec<&str>>()
+                .join(",");
+            self.nmap.scan_ports(args, ip, &ports)
+        }
+    }
+
+    fn cache_hits(&self) -> usize {
+        self.nmap.cache_hits()
+    }
+}
+
+/// Built-in TCP connect scanner, it doesn't need any privileges. Nmap is
+/// only used for service detection on the open ports, when available
+pub struct ConnectScanner {
+    ports: Vec<u16>,
+    limiter: RateLimiter,
+    nmap: Option<NmapScanner>,
+}
+
+impl ConnectScanner {
+    async fn discover(&self, ip: Ipv4Addr) -> Vec<Port> {
+        let mut open_ports: Vec<u16> = stream::iter(self.ports.iter().copied())
+            .map(|port| async move {
+                if misc::interrupted() {
+                    return None;
+                }
+                self.limiter.wait().await;
+                match tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect((ip, port))).await {
+                    Ok(Ok(_)) => Some(port),
+                    _ => None,
+                }
+            })
+            .buffer_unordered(CONNECT_CONCURRENCY)
+            .filter_map(|port| async move { port })
+            .collect()
+            .await;
+        open_ports.sort_unstable();
+        open_ports
+            .into_iter()
+            .map(|port| Port {
+                protocol: String::from("tcp"),
+                portid: port.to_string(),
+                state: State {
+                    state: String::from("open"),
+                    reason: String::from("syn-ack"),
+                    reason_ttl: String::new(),
+                },
+                service: None,
+            })
+            .collect()
+    }
+}
+
+impl PortScanner for ConnectScanner {
+    fn scan(&self, args: &ProcessedArgs, ip: &str) -> ResolvData {
+        let mut scan_data = ResolvData {
+            ip: ip.to_string(),
+            ..ResolvData::default()
+        };
+        let address = match ip.parse::<Ipv4Addr>() {
+            Ok(address) => address,
+            Err(e) => {
+                scan_data.scan_status = ScanStatus::DiscoveryError(format!("invalid IP: {e}"));
+                return scan_data;
+            }
+        };
+
+        let discovery = networking::block_on(async {
+            match args.host_timeout {
+                Some(timeout) => tokio::time::timeout(timeout, self.discover(address))
+                    .await
+                    .ok(),
+                None => Some(self.discover(address).await),
+            }
+        });
+        match discovery {
+            _ if misc::interrupted() => scan_data.scan_status = ScanStatus::Interrupted,
+            None => scan_data.scan_status = ScanStatus::Timeout,
+            Some(ports_data) => match &self.nmap {
+                Some(nmap) if !ports_data.is_empty() => {
+                    let ports = ports_data
+                        .iter()
+                        .map(|port| port.portid.as_str())
+                        .collect::<Vec<&str>>()
+                        .join(",");
+                    return nmap.scan_ports(args, ip, &ports);
+                }
+                _ => {
+                    scan_data.ports_data = ports_data;
+                    scan_data.scan_status = ScanStatus::Ok;
+                }
+            },
+        }
+        scan_data
+    }
+
+    fn cache_hits(&self) -> usize {
+        self.nmap.as_ref().map_or(0, PortScanner::cache_hits)
+    }
+}
+
+/// Doesn't scan anything, the results come from the Nmap XML files of a
+/// directory, e.g. of scans run on another machine
+pub struct ImportScanner {
+    hosts: HashMap<String, ResolvData>,
+}
+
+impl ImportScanner {
+    fn load(args: &ProcessedArgs, dir: &str) -> errors::Result<Self> {
+        let mut hosts: HashMap<String, ResolvData> = HashMap::new();
+        let mut files = 0;
+        for entry in
+            std::fs::read_dir(dir).with_context(|_| format!("Can't read the directory {dir}"))?
+        {
+            let path = entry?.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some("xml") {
+                continue;
+            }
+            let nmap_hosts: NmapHosts = match std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|xml| serde_xml_rs::from_str(&xml).map_err(|e| e.to_string()))
+            {
+                Ok(nmap_hosts) => nmap_hosts,
+                Err(e) => {
+                    warn!(
+                        "Skipping {}, it's not a Nmap XML file: {e}\n",
+                        path.display()
+                    );
+                    continue;
+                }
+            };
+            files += 1;
+            for host in nmap_hosts.hosts() {
+                let ip = match &host.address.addr {
+                    Some(ip) => ip.clone(),
+                    None => continue,
+                };
+                // The XML of an IP found in several files doesn't have all its ports
+                let nmap_xml = (!hosts.contains_key(&ip)).then(|| path.display().to_string());
+                let scan_data = hosts.entry(ip.clone()).or_insert_with(|| ResolvData {
+                    ip,
+                    scan_status: ScanStatus::Ok,
+                    ..ResolvData::default()
+                });
+                scan_data.nmap_xml = nmap_xml;
+                // The same IP can be in several files, e.g. scans of different ports
+                for port in host.open_ports() {
+                    if !scan_data
+                        .ports_data
+                        .iter()
+                        .any(|known| known.portid == port.portid && known.protocol == port.protocol)
+                    {
+                        scan_data.ports_data.push(port);
+                    }
+                }
+                if scan_data.os.is_none() {
+                    scan_data.os = host.best_os_match();
+                }
+                if host.timed_out() {
+                    scan_data.scan_status = ScanStatus::Timeout;
+                }
+            }
+        }
+        if !args.quiet_flag {
+            info!(
+                "Imported the results of {} IPs from {files} Nmap XML files in {dir}.\n",
+                hosts.len()
+            );
+        }
+        Ok(Self { hosts })
+    }
+}
+
+impl PortScanner for ImportScanner {
+    /// IPs that are not in the files are reported as not scanned
+    fn scan(&self, _args: &ProcessedArgs, ip: &str) -> ResolvData {
+        self.hosts.get(ip).cloned().unwrap_or_else(|| ResolvData {
+            ip: ip.to_string(),
+            ..ResolvData::default()
+        })
+    }
+
+    fn cache_hits(&self) -> usize {
+        0
+    }
+}
+
+/// Spreads the connection attempts of all the scanned IPs evenly to honour --rate
+struct RateLimiter {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(rate: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / rate.max(1),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    async fn wait(&self) {
+        let slot = {
+            let mut next = self.next.lock().unwrap_or_else(PoisonError::into_inner);
+            let slot = (*next).max(Instant::now());
+            *next = slot + self.interval;
+            slot
+        };
+        tokio::time::sleep_until(slot.into()).await;
+    }
+}
+
+/// Parse the masscan list output (-oL) into the open ports of every IP, lines
+/// look like `open tcp 80 1.2.3.4 1700000000`
+#[must_use]
+pub fn parse_masscan_list(list: &str) -> HashMap<String, Vec<Port>> {
+    let mut hosts: HashMap<String, Vec<Port>> = HashMap::new();
+    for line in list.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if let ["open", protocol, portid, ip, ..] = fields.as_slice() {
+            hosts.entry((*ip).to_string()).or_default().push(Port {
+                protocol: (*protocol).to_string(),
+                portid: (*portid).to_string(),
+                state: State {
+                    state: String::from("open"),
+                    reason: String::from("masscan"),
+                    reason_ttl: String::new(),
+                },
+                service: None,
+            });
+        }
+    }
+    for ports in hosts.values_mut() {
+        ports.sort_by_key(|port| port.portid.parse::<u16>().unwrap_or_default());
+        ports.dedup();
+    }
+    hosts
+}
+
+/// Run a scanner and wait for it, killing it when the `--host-timeout` (plus a
+/// grace period for the scanner to stop by itself) expires or when unimap is
+/// interrupted. Execution errors and non-zero exit codes are reported with `error`
+pub fn run_command(
+    program: &str,
+    command_args: &[&str],
+    timeout: Option<Duration>,
+    error: fn(String) -> ScanStatus,
+) -> Result<Output, ScanStatus> {
+    if misc::interrupted() {
+        return Err(ScanStatus::Interrupted);
+    }
+
+    let mut child = Command::new(program)
+        .args(command_args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| error(format!("can't execute {program}: {e}")))?;
+
+    // Nmap is very verbose with -dd, the pipes must be drained while it runs
+    let stdout = read_pipe(child.stdout.take());
+    let stderr = read_pipe(child.stderr.take());
+
+    let deadline = timeout.map(|timeout| Instant::now() + timeout + HOST_TIMEOUT_GRACE);
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) => {
+                let timed_out = deadline.is_some_and(|deadline| Instant::now() >= deadline);
+                if timed_out || misc::interrupted() {
+                    // The process may have exited meanwhile, wait() reaps it either way
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(if timed_out {
+                        ScanStatus::Timeout
+                    } else {
+                        ScanStatus::Interrupted
+                    });
+                }
+                thread::sleep(WAIT_INTERVAL);
+            }
+            Err(e) => return Err(error(format!("error waiting for {program} to finish: {e}"))),
+        }
+    };
+
+    let output = Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    };
+
+    if output.status.success() {
+        Ok(output)
+    } else if misc::interrupted() {
+        Err(ScanStatus::Interrupted)
+    } else {
+        Err(error(output_excerpt(&output)))
+    }
+}
+
+fn read_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buffer = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buffer);
+        }
+        buffer
+    })
+}
+
+/// Last lines written by a scanner to stderr (or stdout when stderr is empty),
+/// enough to tell why a scan failed without flooding the logs
+fn output_excerpt(output: &Output) -> String {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let text = if stderr.trim().is_empty() {
+        stdout
+    } else {
+        stderr
+    };
+    let lines: Vec<&str> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+    let mut excerpt = lines[lines.len().saturating_sub(ERROR_LINES)..].join(" | ");
+    if excerpt.len() > ERROR_MAX_LEN {
+        let mut end = ERROR_MAX_LEN;
+        while !excerpt.is_char_boundary(end) {
+            end -= 1;
+        }
+        excerpt.truncate(end);
+        excerpt.push_str("...");
+    }
+    match output.status.code() {
+        Some(code) => format!("exit code {code}: {excerpt}"),
+        None => format!("terminated by signal: {excerpt}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::args::Args,
+        clap::Parser,
+        std::{fs, net::TcpListener, path::PathBuf},
+    };
+
+    /// Writes the -oL output masscan would produce for the IPs of the -iL file
+    #[cfg(unix)]
+    const MASSCAN_STUB: &str = r##"#!/bin/sh
+echo "$@" >> "$(dirname "$0")/calls"
+while [ $# -gt 0 ]; do
+    case "$1" in
+        -iL) targets=$2 ;;
+        -oL) list=$2 ;;
+    esac
+    shift
+done
+echo "#masscan" > "$list"
+while read -r ip; do
+    case "$ip" in
+        192.0.2.1) printf 'open tcp 443 %s 1700000000\nopen tcp 80 %s 1700000000\n' "$ip" "$ip" >> "$list" ;;
+        192.0.2.2) printf 'open tcp 22 %s 1700000000\n' "$ip" >> "$list" ;;
+    esac
+done < "$targets"
+echo "# end" >> "$list"
+"##;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("unimap-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn open_port(portid: &str, reason: &str) -> Port {
+        Port {
+            protocol: String::from("tcp"),
+            portid: portid.to_string(),
+            state: State {
+                state: String::from("open"),
+                reason: reason.to_string(),
+                reason_ttl: String::new(),
+            },
+            service: None,
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn masscan_runs_once_for_all_ips() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = temp_dir("masscan");
+        let stub = dir.join("masscan");
+        fs::write(&stub, MASSCAN_STUB).unwrap();
+        fs::set_permissions(&stub, fs::Permissions::from_mode(0o755)).unwrap();
+        let args = Args::parse_from([
+            "unimap",
+            "--scanner",
+            "masscan",
+            "--masscan-path",
+            &stub.display().to_string(),
+            "--logs-dir",
+            &dir.display().to_string(),
+            "--fast-scan",
+            "--quiet",
+        ])
+        .into_processed_args();
+
+        let scanner = new_scanner(&args, Vec::new()).unwrap();
+        scanner.prepare(&args, &["192.0.2.1", "192.0.2.2", "192.0.2.3"]);
+        let first = scanner.scan(&args, "192.0.2.1");
+        let second = scanner.scan(&args, "192.0.2.2");
+        let third = scanner.scan(&args, "192.0.2.3");
+
+        assert_eq!(first.scan_status, ScanStatus::Ok);
+        assert_eq!(
+            first.ports_data,
+            vec![open_port("80", "masscan"), open_port("443", "masscan")]
+        );
+        assert_eq!(second.ports_data, vec![open_port("22", "masscan")]);
+        assert_eq!(third.scan_status, ScanStatus::Ok);
+        assert!(third.ports_data.is_empty());
+        let calls = fs::read_to_string(dir.join("calls")).unwrap();
+        assert_eq!(calls.lines().count(), 1);
+        assert!(calls.contains("--top-ports 1000"));
+        assert!(!dir.join("unimap-masscan.masscan").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn masscan_failure_is_reported_for_every_ip() {
+        let dir = temp_dir("masscan-missing");
+        let args = Args::parse_from([
+            "unimap",
+            "--scanner",
+            "masscan",
+            "--masscan-path",
+            &dir.join("missing").display().to_string(),
+            "--logs-dir",
+            &dir.display().to_string(),
+            "--quiet",
+        ])
+        .into_processed_args();
+
+        let scanner = new_scanner(&args, Vec::new()).unwrap();
+        scanner.prepare(&args, &["192.0.2.1", "192.0.2.2"]);
+        for ip in ["192.0.2.1", "192.0.2.2"] {
+            assert!(matches!(
+                scanner.scan(&args, ip).scan_status,
+                ScanStatus::DiscoveryError(_)
+            ));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn connect_scan_finds_the_listening_ports() {
+        let listeners: Vec<TcpListener> = (0..2)
+            .map(|_| TcpListener::bind("127.0.0.1:0").unwrap())
+            .collect();
+        let mut open: Vec<u16> = listeners
+            .iter()
+            .map(|listener| listener.local_addr().unwrap().port())
+            .collect();
+        open.sort_unstable();
+        // A port that was just released is closed
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let scanner = ConnectScanner {
+            ports: vec![open[1], closed, open[0]],
+            limiter: RateLimiter::new(1000),
+            nmap: None,
+        };
+        let ports_data = networking::block_on(scanner.discover(Ipv4Addr::LOCALHOST));
+
+        let expected: Vec<Port> = open
+            .iter()
+            .map(|port| open_port(&port.to_string(), "syn-ack"))
+            .collect();
+        assert_eq!(ports_data, expected);
+    }
+
+    #[test]
+    fn connect_scan_without_nmap_reports_the_open_ports() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port().to_string();
+        let args = Args::parse_from([
+            "unimap",
+            "--scanner",
+            "connect",
+            "--fast-scan",
+            "--ports",
+            &port,
+        ])
+        .into_processed_args();
+
+        let scan_data = new_scanner(&args, Vec::new())
+            .unwrap()
+            .scan(&args, "127.0.0.1");
+
+        assert_eq!(scan_data.scan_status, ScanStatus::Ok);
+        assert_eq!(scan_data.ports_data, vec![open_port(&port, "syn-ack")]);
+    }
+}
@@ -0,0 +1,296 @@
+use {
+    crate::{
+        args::ProcessedArgs,
+        errors::{Result, ResultExt},
+        structs::ResolvData,
+    },
+    failure::bail,
+    log::{info, warn},
+    std::{
+        collections::HashMap,
+        fs::{self, File, OpenOptions},
+        io::Write,
+        path::Path,
+        sync::Mutex,
+    },
+};
+
+/// Name of the state file in the logs directory
+pub const STATE_FILE: &str = "unimap-state.jsonl";
+
+/// A line of the state file
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Entry {
+    /// Scan options of the run, the first line of the file
+    Options(String),
+    Resolved {
+        host: String,
+        data: ResolvData,
+    },
+    Scanned {
+        ip: String,
+        data: ResolvData,
+    },
+}
+
+/// Checkpoint of the run, every completed resolution and IP scan is appended
+/// to the state file as soon as it finishes so --resume can skip them
+pub struct State {
+    path: String,
+    file: Mutex<File>,
+    resolved: HashMap<String, ResolvData>,
+    scanned: HashMap<String, ResolvData>,
+}
+
+impl State {
+    /// Start a new state file, or load the existing one with --resume
+    pub fn open(args: &ProcessedArgs) -> Result<Self> {
+        let path = Path::new(&args.logs_dir)
+            .join(STATE_FILE)
+            .to_string_lossy()
+            .to_string();
+        let options = scan_options(args);
+        let mut state = Self {
+            file: Mutex::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .with_context(|_| format!("Can't open the state file {path}"))?,
+            ),
+            path,
+            resolved: HashMap::new(),
+            scanned: HashMap::new(),
+        };
+
+        if args.resume && fs::metadata(&state.path).map_or(0, |metadata| metadata.len()) > 0 {
+            state.load(&options)?;
+            if !args.quiet_flag {
+                info!(
+                    "Resuming from {}: {} targets already resolved and {} IPs already scanned.\n",
+                    state.path,
+                    state.resolved.len(),
+                    state.scanned.len()
+                );
+            }
+        } else {
+            if args.resume && !args.quiet_flag {
+                info!(
+                    "No previous run to resume in {}, starting a new one.\n",
+                    args.logs_dir
+                );
+            }
+            let file = state.file.get_mut().expect("State file lock poisoned");
+            file.set_len(0)
+                .with_context(|_| format!("Can't truncate the state file {}", state.path))?;
+            state.append(&Entry::Options(options));
+        }
+        Ok(state)
+    }
+
+    fn load(&mut self, options: &str) -> Result<()> {
+        let content = fs::read_to_string(&self.path)
+            .with_context(|_| format!("Can't read the state file {}", self.path))?;
+        for (number, line) in content.lines().enumerate() {
+            // The last line is cut when the previous run was killed while writing it
+            let entry = match serde_json::from_str(line) {
+                Ok(entry) => entry,
+                Err(e) => {
+                    warn!(
+                        "Ignoring line {} of the state file {}: {e}\n",
+                        number + 1,
+                        self.path
+                    );
+                    continue;
+                }
+            };
+            match entry {
+                Entry::Options(saved) if saved != options => bail!(
+                    "The run saved in {} used different scan options ({}), run it again with the same options or without --resume to start over",
+                    self.path,
+                    saved
+                ),
+                Entry::Options(_) => (),
+                Entry::Resolved { host, data } => {
+                    self.resolved.insert(host, data);
+                }
+                Entry::Scanned { ip, data } => {
+                    self.scanned.insert(ip, data);
+                }
+            }
+        }
+        // Start the new entries on their own line after a cut one
+        if !content.ends_with('\n') {
+            self.file
+                .get_mut()
+                .expect("State file lock poisoned")
+                .write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    /// Resolution of `host` saved by the previous run
+    #[must_use]
+    pub fn resolved(&self, host: &str) -> Option<&ResolvData> {
+        self.resolved.get(host)
+    }
+
+    /// Scan of `ip` saved by the previous run
+    #[must_use]
+    pub fn scanned(&self, ip: &str) -> Option<&ResolvData> {
+        self.scanned.get(ip)
+    }
+
+    pub fn save_resolution(&self, host: &str, data: &ResolvData) {
+        self.append(&Entry::Resolved {
+            host: host.to_owned(),
+            data: data.clone(),
+        });
+    }
+
+    /// Save the scan of `ip`, failed scans are left out so they are retried
+    pub fn save_scan(&self, ip: &str, data: &ResolvData) {
+        if !data.scan_status.is_failure() {
+            self.append(&Entry::Scanned {
+                ip: ip.to_owned(),
+                data: data.clone(),
+            });
+        }
+    }
+
+    fn append(&self, entry: &Entry) {
+        let result = serde_json::to_string(entry)
+            .map_err(failure::Error::from)
+            .and_then(|line| {
+                let mut file = self.file.lock().expect("State file lock poisoned");
+                Ok(file.write_all(format!("{line}\n").as_bytes())?)
+            });
+        if let Err(e) = result {
+            warn!("Error writing the state file {}: {e}\n", self.path);
+        }
+    }
+
+    /// Delete the state file once the run is complete
+    pub fn remove(self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Error removing the state file {}: {e}\n", self.path);
+        }
+    }
+}
+
+/// Options that change the IPs that are scanned or their results, a resumed
+/// run must use the same
+fn scan_options(args: &ProcessedArgs) -> String {
+    format!(
+        "scanner={:?} ports={} fast_scan={} os_detection={} host_timeout={:?} scan_type={} min_rate={} rate={} cdn={:?} cdn_ranges={:?} cloud_ranges={:?} cloud_filter={:?} resolutions={:?}",
+        args.scanner,
+        args.ports,
+        args.fast_scan,
+        args.os_detection,
+        args.host_timeout,
+        args.scan_type,
+        args.min_rate,
+        args.rate,
+        args.cdn,
+        args.cdn_ranges,
+        args.cloud_ranges,
+        args.cloud_filter,
+        args.resolutions
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::args::Args, clap::Parser, std::path::PathBuf};
+
+    fn logs_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("unimap-state-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn args(dir: &Path, options: &[&str]) -> ProcessedArgs {
+        let dir = dir.display().to_string();
+        let mut args = vec!["unimap", "--logs-dir", &dir, "--quiet"];
+        args.extend_from_slice(options);
+        Args::parse_from(args).into_processed_args()
+    }
+
+    fn scanned(ip: &str) -> ResolvData {
+        ResolvData {
+            ip: ip.to_string(),
+            ..ResolvData::default()
+        }
+    }
+
+    #[test]
+    fn scan_options_include_everything_that_changes_the_results() {
+        let dir = logs_dir("options");
+        let base = scan_options(&args(&dir, &[]));
+        for options in [
+            &["--ports", "22,80"][..],
+            &["--fast-scan"],
+            &["--host-timeout", "5m"],
+            &["--min-rate", "1000"],
+            &["--scanner", "masscan"],
+            &["--rate", "5000"],
+            &["--cdn", "skip"],
+            &["--cdn", "tag", "--cdn-ranges", "cdn.txt"],
+            &["--cloud-ranges", "aws.json"],
+            &["--cloud-ranges", "aws.json", "--cloud-filter", "only"],
+            &["--resolutions", "massdns.txt"],
+        ] {
+            assert_ne!(scan_options(&args(&dir, options)), base, "{options:?}");
+        }
+        let mut connect = args(&dir, &[]);
+        connect.scan_type = String::from("-sT");
+        assert_ne!(scan_options(&connect), base);
+        // Options that only change the outputs or the enrichments
+        assert_eq!(scan_options(&args(&dir, &["--banners", "--json"])), base);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn resume_with_other_options_is_rejected() {
+        let dir = logs_dir("mismatch");
+        let state = State::open(&args(&dir, &["--ports", "22"])).unwrap();
+        state.save_scan("192.0.2.1", &scanned("192.0.2.1"));
+        drop(state);
+
+        let other = State::open(&args(&dir, &["--ports", "22,80", "--resume"]));
+        let same = State::open(&args(&dir, &["--ports", "22", "--resume"]));
+        fs::remove_dir_all(&dir).unwrap();
+
+        let error = other.err().unwrap().to_string();
+        assert!(error.contains("different scan options"), "{}", error);
+        assert!(error.contains("ports=22 "), "{}", error);
+        assert!(same.unwrap().scanned("192.0.2.1").is_some());
+    }
+
+    #[test]
+    fn cut_last_line_is_recovered() {
+        let dir = logs_dir("cut");
+        let state = State::open(&args(&dir, &[])).unwrap();
+        state.save_resolution("www.example.com", &scanned("192.0.2.1"));
+        state.save_scan("192.0.2.1", &scanned("192.0.2.1"));
+        drop(state);
+        // Kill the run in the middle of the last line
+        let path = dir.join(STATE_FILE);
+        let content = fs::read_to_string(&path).unwrap();
+        fs::write(&path, &content[..content.len() - 10]).unwrap();
+
+        let state = State::open(&args(&dir, &["--resume"])).unwrap();
+        assert!(state.resolved("www.example.com").is_some());
+        assert!(state.scanned("192.0.2.1").is_none());
+        state.save_scan("192.0.2.2", &scanned("192.0.2.2"));
+        drop(state);
+
+        let state = State::open(&args(&dir, &["--resume"])).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(state.resolved("www.example.com").is_some());
+        assert!(state.scanned("192.0.2.1").is_none());
+        assert!(state.scanned("192.0.2.2").is_some());
+    }
+}
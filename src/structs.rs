@@ -1,15 +1,116 @@
-use crate::nmap::Port;
+use {
+    crate::{
+        asn::AsnInfo,
+        cloud::CloudInfo,
+        http_probe::HttpProbe,
+        nmap::{OsMatch, Port},
+        tls::TlsInfo,
+    },
+    std::fmt,
+};
 
-#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct ResolvData {
+    /// Scanned IP, the first address of the host
     pub ip: String,
+    /// Every IPv4 address the host resolved to
+    pub ips: Vec<String>,
+    pub ptr: Vec<String>,
+    pub asn: Option<AsnInfo>,
+    pub cdn: Option<String>,
+    pub cloud: Option<CloudInfo>,
     pub ports_data: Vec<Port>,
+    pub os: Option<OsMatch>,
+    pub scan_status: ScanStatus,
+    pub http_probes: Vec<HttpProbe>,
+    pub tls: Vec<TlsInfo>,
+    /// Nmap XML file of the scan, written or reused from --scan-cache by this run
+    pub nmap_xml: Option<String>,
 }
 impl ResolvData {
     pub const fn default() -> Self {
         Self {
             ip: String::new(),
+            ips: Vec::new(),
+            ptr: Vec::new(),
+            asn: None,
+            cdn: None,
+            cloud: None,
             ports_data: Vec::new(),
+            os: None,
+            scan_status: ScanStatus::NotScanned,
+            http_probes: Vec::new(),
+            tls: Vec::new(),
+            nmap_xml: None,
+        }
+    }
+}
+
+/// Outcome of the port scan of an IP
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum ScanStatus {
+    /// The IP was not scanned, e.g. it's empty or private
+    NotScanned,
+    Ok,
+    /// Nmap could not be executed or exited with an error, with an excerpt of its output
+    NmapError(String),
+    /// The port discovery backend could not be executed or exited with an error
+    DiscoveryError(String),
+    /// The scan did not finish in the allowed time
+    Timeout,
+    /// Nmap finished but its XML output could not be parsed
+    ParseError(String),
+    /// The scan was cancelled with Ctrl-C
+    Interrupted,
+}
+
+impl ScanStatus {
+    /// Machine-friendly name of the status used by the structured outputs
+    #[must_use]
+    pub const fn code(&self) -> &'static str {
+        match self {
+            Self::NotScanned => "not_scanned",
+            Self::Ok => "ok",
+            Self::NmapError(_) => "nmap_error",
+            Self::DiscoveryError(_) => "discovery_error",
+            Self::Timeout => "timeout",
+            Self::ParseError(_) => "parse_error",
+            Self::Interrupted => "interrupted",
+        }
+    }
+
+    /// Error message of the failed statuses
+    #[must_use]
+    pub fn detail(&self) -> Option<&str> {
+        match self {
+            Self::NmapError(e) | Self::DiscoveryError(e) | Self::ParseError(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub const fn is_failure(&self) -> bool {
+        matches!(
+            self,
+            Self::NmapError(_)
+                | Self::DiscoveryError(_)
+                | Self::Timeout
+                | Self::ParseError(_)
+                | Self::Interrupted
+        )
+    }
+}
+
+impl fmt::Display for ScanStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotScanned => write!(f, "NOT SCANNED"),
+            Self::Ok => write!(f, "OK"),
+            Self::NmapError(e) => write!(f, "NMAP ERROR: {e}"),
+            Self::DiscoveryError(e) => write!(f, "DISCOVERY ERROR: {e}"),
+            Self::Timeout => write!(f, "TIMEOUT"),
+            Self::ParseError(e) => write!(f, "PARSE ERROR: {e}"),
+            Self::Interrupted => write!(f, "INTERRUPTED"),
         }
     }
 }
@@ -0,0 +1,277 @@
+use {
+    crate::{misc, structs::ResolvData},
+    openssl::{
+        hash::MessageDigest,
+        nid::Nid,
+        ssl::{SslConnector, SslMethod, SslVerifyMode},
+        x509::{X509NameRef, X509Ref},
+    },
+    rayon::prelude::*,
+    std::{
+        collections::HashSet,
+        convert::TryFrom,
+        net::{IpAddr, SocketAddr, TcpStream},
+        time::Duration,
+    },
+};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Certificate chain served on a port, leaf certificate first
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct TlsInfo {
+    pub port: String,
+    pub chain: Vec<Certificate>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct Certificate {
+    pub subject: String,
+    #[serde(default)]
+    pub common_name: Option<String>,
+    pub sans: Vec<String>,
+    pub issuer: String,
+    pub not_before: String,
+    pub not_after: String,
+    pub fingerprint_sha256: String,
+}
+
+impl TlsInfo {
+    #[must_use]
+    pub fn leaf(&self) -> Option<&Certificate> {
+        self.chain.first()
+    }
+}
+
+/// Try a TLS handshake on every open TCP port of the IP and keep the
+/// certificate chains of the ones that succeed. `server_name` is sent as SNI
+pub fn collect_certificates(scan_data: &mut ResolvData, server_name: Option<&str>) {
+    let ip: IpAddr = match scan_data.ip.parse() {
+        Ok(ip) => ip,
+        Err(_) => return,
+    };
+    // The handshakes can take up to the connect and IO timeouts, do them in parallel
+    scan_data.tls = scan_data
+        .ports_data
+        .par_iter()
+        .filter(|port| port.protocol == "tcp" && !misc::interrupted())
+        .filter_map(|port| {
+            let address = SocketAddr::new(ip, port.portid.parse().ok()?);
+            get_chain(address, server_name).map(|chain| TlsInfo {
+                port: port.portid.clone(),
+                chain,
+            })
+        })
+        .collect();
+}
+
+fn get_chain(address: SocketAddr, server_name: Option<&str>) -> Option<Vec<Certificate>> {
+    let stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT).ok()?;
+    stream.set_read_timeout(Some(IO_TIMEOUT)).ok()?;
+    stream.set_write_timeout(Some(IO_TIMEOUT)).ok()?;
+
+    let mut connector = SslConnector::builder(SslMethod::tls()).ok()?;
+    // Expired or self-signed certificates are exactly what we want to see
+    connector.set_verify(SslVerifyMode::NONE);
+    let config = connector
+        .build()
+        .configure()
+        .ok()?
+        .use_server_name_indication(server_name.is_some())
+        .verify_hostname(false);
+    let stream = config
+        .connect(server_name.unwrap_or_default(), stream)
+        .ok()?;
+
+    // On the client side the peer chain includes the leaf certificate
+    let chain: Vec<Certificate> = stream
+        .ssl()
+        .peer_cert_chain()
+        .map(|chain| chain.iter().map(certificate_info).collect())
+        .unwrap_or_default();
+    if chain.is_empty() {
+        stream
+            .ssl()
+            .peer_certificate()
+            .map(|leaf| vec![certificate_info(&leaf)])
+    } else {
+        Some(chain)
+    }
+}
+
+fn certificate_info(certificate: &X509Ref) -> Certificate {
+    Certificate {
+        subject: name_to_string(certificate.subject_name()),
+        common_name: certificate
+            .subject_name()
+            .entries_by_nid(Nid::COMMONNAME)
+            .next()
+            .and_then(|entry| entry.data().as_utf8().ok())
+            .map(|value| value.to_string()),
+        sans: certificate
+            .subject_alt_names()
+            .map(|names| {
+                names
+                    .iter()
+                    .filter_map(|name| {
+                        name.dnsname()
+                            .map(str::to_string)
+                            .or_else(|| name.ipaddress().and_then(ip_to_string))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default(),
+        issuer: name_to_string(certificate.issuer_name()),
+        not_before: certificate.not_before().to_string(),
+        not_after: certificate.not_after().to_string(),
+        fingerprint_sha256: certificate
+            .digest(MessageDigest::sha256())
+            .map(|digest| {
+                digest
+                    .iter()
+                    .map(|byte| format!("{byte:02X}"))
+                    .collect::<Vec<String>>()
+                    .join(":")
+            })
+            .unwrap_or_default(),
+    }
+}
+
+fn ip_to_string(ip: &[u8]) -> Option<String> {
+    if let Ok(ip) = <[u8; 4]>::try_from(ip) {
+        Some(IpAddr::from(ip).to_string())
+    } else {
+        <[u8; 16]>::try_from(ip)
+            .ok()
+            .map(|ip| IpAddr::from(ip).to_string())
+    }
+}
+
+fn name_to_string(name: &X509NameRef) -> String {
+    name.entries()
+        .map(|entry| {
+            let key = entry.object().nid().short_name().unwrap_or("?");
+            let value = entry
+                .data()
+                .as_utf8()
+                .map(|value| value.to_string())
+                .unwrap_or_default();
+            format!("{key}={value}")
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// DNS names found in the certificates (common names and SANs) that are not
+/// part of the targets. Wildcards can't be resolved, so they are skipped
+#[must_use]
+pub fn new_hostnames<'a, I, S>(data: I, targets: &HashSet<String, S>) -> HashSet<String>
+where
+    I: IntoIterator<Item = &'a ResolvData>,
+    S: std::hash::BuildHasher,
+{
+    data.into_iter()
+        .flat_map(|resolv_data| &resolv_data.tls)
+        .filter_map(TlsInfo::leaf)
+        .flat_map(|certificate| {
+            certificate
+                .sans
+                .iter()
+                .cloned()
+                .chain(certificate.common_name.iter().cloned())
+        })
+        .map(|name| name.trim_end_matches('.').to_lowercase())
+        .filter(|name| {
+            !name.starts_with("*.")
+                && name.parse::<IpAddr>().is_err()
+                && name.contains('.')
+                && !targets.contains(name)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        openssl::{
+            asn1::Asn1Time,
+            pkey::PKey,
+            rsa::Rsa,
+            x509::{extension::SubjectAlternativeName, X509Name, X509},
+        },
+    };
+
+    fn self_signed(entries: &[(&str, &str)], sans: &[&str]) -> X509 {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509Name::builder().unwrap();
+        for (field, value) in entries {
+            name.append_entry_by_text(field, value).unwrap();
+        }
+        let name = name.build();
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(30).unwrap())
+            .unwrap();
+        if !sans.is_empty() {
+            let mut alt_names = SubjectAlternativeName::new();
+            for san in sans {
+                alt_names.dns(san);
+            }
+            let extension = alt_names
+                .build(&builder.x509v3_context(None, None))
+                .unwrap();
+            builder.append_extension(extension).unwrap();
+        }
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        builder.build()
+    }
+
+    #[test]
+    fn common_name_is_read_from_the_subject() {
+        let certificate = certificate_info(&self_signed(
+            &[
+                ("O", "Example, CN=fake.example.com"),
+                ("CN", "www.example.com"),
+            ],
+            &["api.example.com", "*.example.com"],
+        ));
+        assert_eq!(certificate.common_name.as_deref(), Some("www.example.com"));
+        assert_eq!(certificate.sans, vec!["api.example.com", "*.example.com"]);
+
+        let certificate = certificate_info(&self_signed(&[("O", "Example")], &[]));
+        assert_eq!(certificate.common_name, None);
+    }
+
+    #[test]
+    fn new_hostnames_from_the_leaf_certificate() {
+        let leaf = certificate_info(&self_signed(
+            &[
+                ("O", "Example, CN=fake.example.com"),
+                ("CN", "WWW.example.com."),
+            ],
+            &["api.example.com", "*.example.com", "known.example.com"],
+        ));
+        let issuer = certificate_info(&self_signed(&[("CN", "ca.example.com")], &[]));
+        let data = ResolvData {
+            tls: vec![TlsInfo {
+                port: "443".to_string(),
+                chain: vec![leaf, issuer],
+            }],
+            ..ResolvData::default()
+        };
+        let targets: HashSet<String> = std::iter::once("known.example.com".to_string()).collect();
+
+        let mut found: Vec<String> = new_hostnames(&[data], &targets).into_iter().collect();
+        found.sort();
+        assert_eq!(found, vec!["api.example.com", "www.example.com"]);
+    }
+}